@@ -1,6 +0,0 @@
-CREATE TABLE adressen_28992 
-    (
-        point geometry(Point, 28992) NOT NULL, 
-        postcode character varying(6) NOT NULL
-    );
-
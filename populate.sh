@@ -21,29 +21,14 @@ createdb -h $host -U tgbag $database_name
 psql -h $host -U tgbag $database_name -c 'CREATE EXTENSION IF NOT EXISTS postgis'
 psql -h $host -U tgbag $database_name -c 'CREATE EXTENSION IF NOT EXISTS postgis_topology'
 
-psql -h $host -U tgbag $database_name < before.sql
-
-
-
 cargo build --release &
 
 # We use `&` to spawn child processes (which run in parallel). Now wait until all have completed
 wait
 
-num_name=`unzip -Z1 data/lvbag-extract-nl.zip | grep "NUM"`
-vbo_name=`unzip -Z1 data/lvbag-extract-nl.zip | grep "VBO"`
-
-# unzip -j $data/lvbag-extract-nl.zip $num_name $vbo_name -d $data
-
-# mv $data/$num_name data/num.zip
-# mv $data/$vbo_name data/vbo.zip
-
-cargo run --release generate --source $data --user tgbag --password tgbag --host "$host" --dbname $database_name
-
-psql -h $host -U tgbag $database_name < after.sql
-
-# rm data/num.zip
-# rm data/vbo.zip
+# the VBO and NUM zip files are read straight from the national extract (or unpacked next to it,
+# when they are compressed)
+cargo run --release generate --source $data/lvbag-extract-nl.zip --user tgbag --password tgbag --host "$host" --dbname $database_name
 
 # remove bag zip
 # rm $data/lvbag-extract-nl.zip
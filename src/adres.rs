@@ -0,0 +1,159 @@
+use std::fmt::Display;
+
+use crate::point::MillimeterPoint;
+use crate::postcode::Postcode;
+
+/// A house number, with its optional letter and addition (e.g. `12A-bis`)
+///
+/// The ordering is the natural one: `12 < 12-1 < 12A < 12B < 13`
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct Huisnummer {
+    pub nummer: u32,
+    pub letter: Option<u8>,
+    pub toevoeging: Option<Toevoeging>,
+}
+
+impl Huisnummer {
+    pub const fn new(nummer: u32) -> Self {
+        Self {
+            nummer,
+            letter: None,
+            toevoeging: None,
+        }
+    }
+}
+
+impl Display for Huisnummer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.nummer)?;
+
+        if let Some(letter) = self.letter {
+            write!(f, "{}", letter as char)?;
+        }
+
+        if let Some(toevoeging) = self.toevoeging {
+            write!(f, "-{}", toevoeging)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A huisnummertoevoeging: at most 4 alphanumeric characters
+///
+/// Stored inline and padded with zero bytes, so a `Huisnummer` is `Copy`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub struct Toevoeging {
+    bytes: [u8; 4],
+}
+
+impl Toevoeging {
+    /// The toevoeging padded with zero bytes. All zeroes means "no toevoeging".
+    pub const fn to_bytes(self) -> [u8; 4] {
+        self.bytes
+    }
+
+    pub const fn from_bytes(bytes: [u8; 4]) -> Option<Self> {
+        if bytes[0] == 0 {
+            None
+        } else {
+            Some(Self { bytes })
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        let length = self.bytes.iter().position(|b| *b == 0).unwrap_or(4);
+
+        // only ascii alphanumerics are ever stored
+        std::str::from_utf8(&self.bytes[..length]).unwrap_or_default()
+    }
+}
+
+impl Display for Toevoeging {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::fmt::Debug for Toevoeging {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+impl TryFrom<&str> for Toevoeging {
+    type Error = ();
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        if value.is_empty() || value.len() > 4 || !value.bytes().all(|b| b.is_ascii_alphanumeric())
+        {
+            return Err(());
+        }
+
+        let mut bytes = [0; 4];
+        bytes[..value.len()].copy_from_slice(value.as_bytes());
+
+        Ok(Self { bytes })
+    }
+}
+
+/// A single adres: a Verblijfsobject joined with its (main) Nummeraanduiding
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Adres {
+    /// identificatie of the Nummeraanduiding
+    pub identificatie: u64,
+    /// identificatie of the Verblijfsobject
+    pub verblijfsobject: u64,
+    pub postcode: Postcode,
+    pub huisnummer: Huisnummer,
+    /// the geopunt of the Verblijfsobject
+    pub point: MillimeterPoint,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let huisnummer = Huisnummer {
+            nummer: 12,
+            letter: Some(b'A'),
+            toevoeging: Some(Toevoeging::try_from("bis").unwrap()),
+        };
+
+        assert_eq!("12A-bis", huisnummer.to_string());
+        assert_eq!("7", Huisnummer::new(7).to_string());
+    }
+
+    #[test]
+    fn ordering() {
+        let toevoeging = Toevoeging::try_from("1").ok();
+
+        let mut input = [
+            Huisnummer::new(13),
+            Huisnummer {
+                nummer: 12,
+                letter: Some(b'A'),
+                toevoeging: None,
+            },
+            Huisnummer {
+                nummer: 12,
+                letter: None,
+                toevoeging,
+            },
+            Huisnummer::new(12),
+        ];
+        input.sort();
+
+        let pretty: Vec<String> = input.iter().map(|h| h.to_string()).collect();
+        assert_eq!(vec!["12", "12-1", "12A", "13"], pretty);
+    }
+
+    #[test]
+    fn invalid_toevoeging() {
+        assert!(Toevoeging::try_from("").is_err());
+        assert!(Toevoeging::try_from("abcde").is_err());
+        assert!(Toevoeging::try_from("a b").is_err());
+    }
+}
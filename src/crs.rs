@@ -0,0 +1,276 @@
+// Conversion between RD New (EPSG:28992) and WGS84 (EPSG:4326)
+//
+// Uses the polynomial approximation by Schreutelaar and Strang van Hees, which is accurate to
+// within a meter (usually much better) in and around the Netherlands. That is plenty for our
+// purposes, and much simpler (and faster) than the full RDNAPTRANS procedure.
+
+use crate::point::{Coordinates, MillimeterPoint, Point};
+
+/// A point in RD New (EPSG:28992), in meters
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct RdPoint {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// A point in WGS84 (EPSG:4326), in degrees
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Wgs84Point {
+    pub lon: f64,
+    pub lat: f64,
+}
+
+impl RdPoint {
+    pub const fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+}
+
+impl Wgs84Point {
+    pub const fn new(lon: f64, lat: f64) -> Self {
+        Self { lon, lat }
+    }
+}
+
+/// A coordinate reference system that output can be written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Crs {
+    /// RD New, EPSG:28992
+    Rd,
+    /// WGS84, EPSG:4326
+    Wgs84,
+}
+
+impl Crs {
+    pub const fn epsg(self) -> u32 {
+        match self {
+            Crs::Rd => 28992,
+            Crs::Wgs84 => 4326,
+        }
+    }
+
+    pub const fn from_epsg(epsg: u32) -> Option<Self> {
+        match epsg {
+            28992 => Some(Crs::Rd),
+            4326 => Some(Crs::Wgs84),
+            _ => None,
+        }
+    }
+
+    /// The coordinates of `point` in this CRS: `(x, y)` for RD New, `(lon, lat)` for WGS84
+    pub fn project(self, point: RdPoint) -> (f64, f64) {
+        match self {
+            Crs::Rd => (point.x, point.y),
+            Crs::Wgs84 => {
+                let point = rd_to_wgs84(point);
+                (point.lon, point.lat)
+            }
+        }
+    }
+}
+
+/// A point as little endian well-known binary (WKB), the geometry encoding of both GeoPackage and
+/// GeoParquet
+pub fn wkb_point(x: f64, y: f64) -> [u8; 21] {
+    let mut wkb = [0; 21];
+
+    // little endian, geometry type 1 (Point)
+    wkb[0] = 1;
+    wkb[1..5].copy_from_slice(&1u32.to_le_bytes());
+    wkb[5..13].copy_from_slice(&x.to_le_bytes());
+    wkb[13..21].copy_from_slice(&y.to_le_bytes());
+
+    wkb
+}
+
+/// The reference point of both approximations: the Onze Lieve Vrouwetoren in Amersfoort
+const RD_ORIGIN: RdPoint = RdPoint::new(155_000.0, 463_000.0);
+const WGS84_ORIGIN: Wgs84Point = Wgs84Point::new(5.38720621, 52.15517440);
+
+/// `(p, q, coefficient)` for the latitude, in terms of `dx^p * dy^q`
+const K: [(i32, i32, f64); 11] = [
+    (0, 1, 3235.65389),
+    (2, 0, -32.58297),
+    (0, 2, -0.24750),
+    (2, 1, -0.84978),
+    (0, 3, -0.06550),
+    (2, 2, -0.01709),
+    (1, 0, -0.00738),
+    (4, 0, 0.00530),
+    (2, 3, -0.00039),
+    (4, 1, 0.00033),
+    (1, 1, -0.00012),
+];
+
+/// `(p, q, coefficient)` for the longitude, in terms of `dx^p * dy^q`
+const L: [(i32, i32, f64); 12] = [
+    (1, 0, 5260.52916),
+    (1, 1, 105.94684),
+    (1, 2, 2.45656),
+    (3, 0, -0.81885),
+    (1, 3, 0.05594),
+    (3, 1, -0.05607),
+    (0, 1, 0.01199),
+    (3, 2, -0.00256),
+    (1, 4, 0.00128),
+    (0, 2, 0.00022),
+    (2, 0, -0.00022),
+    (5, 0, 0.00026),
+];
+
+/// `(p, q, coefficient)` for x, in terms of `dlat^p * dlon^q`
+const R: [(i32, i32, f64); 9] = [
+    (0, 1, 190094.945),
+    (1, 1, -11832.228),
+    (2, 1, -114.221),
+    (0, 3, -32.391),
+    (1, 0, -0.705),
+    (3, 1, -2.340),
+    (1, 3, -0.608),
+    (0, 2, -0.008),
+    (2, 3, 0.148),
+];
+
+/// `(p, q, coefficient)` for y, in terms of `dlat^p * dlon^q`
+const S: [(i32, i32, f64); 10] = [
+    (1, 0, 309056.544),
+    (0, 2, 3638.893),
+    (2, 0, 73.077),
+    (1, 2, -157.984),
+    (3, 0, 59.788),
+    (0, 1, 0.433),
+    (2, 2, -6.439),
+    (1, 1, -0.032),
+    (0, 4, 0.092),
+    (1, 4, -0.054),
+];
+
+fn polynomial(coefficients: &[(i32, i32, f64)], a: f64, b: f64) -> f64 {
+    coefficients
+        .iter()
+        .map(|(p, q, c)| c * a.powi(*p) * b.powi(*q))
+        .sum()
+}
+
+pub fn rd_to_wgs84(point: RdPoint) -> Wgs84Point {
+    let dx = (point.x - RD_ORIGIN.x) * 1e-5;
+    let dy = (point.y - RD_ORIGIN.y) * 1e-5;
+
+    // the polynomials give arc seconds
+    let lat = WGS84_ORIGIN.lat + polynomial(&K, dx, dy) / 3600.0;
+    let lon = WGS84_ORIGIN.lon + polynomial(&L, dx, dy) / 3600.0;
+
+    Wgs84Point { lon, lat }
+}
+
+pub fn wgs84_to_rd(point: Wgs84Point) -> RdPoint {
+    // differences in units of 10_000 arc seconds
+    let dlat = 0.36 * (point.lat - WGS84_ORIGIN.lat);
+    let dlon = 0.36 * (point.lon - WGS84_ORIGIN.lon);
+
+    let x = RD_ORIGIN.x + polynomial(&R, dlat, dlon);
+    let y = RD_ORIGIN.y + polynomial(&S, dlat, dlon);
+
+    RdPoint { x, y }
+}
+
+impl From<Wgs84Point> for RdPoint {
+    fn from(point: Wgs84Point) -> Self {
+        wgs84_to_rd(point)
+    }
+}
+
+impl From<RdPoint> for Wgs84Point {
+    fn from(point: RdPoint) -> Self {
+        rd_to_wgs84(point)
+    }
+}
+
+/// A stored `Point` is always in RD New
+impl From<Point> for RdPoint {
+    fn from(point: Point) -> Self {
+        RdPoint::new(point.x as f64, point.y as f64)
+    }
+}
+
+impl From<RdPoint> for Point {
+    fn from(point: RdPoint) -> Self {
+        Point::new(point.x as f32, point.y as f32)
+    }
+}
+
+impl From<Point> for Wgs84Point {
+    fn from(point: Point) -> Self {
+        rd_to_wgs84(point.into())
+    }
+}
+
+impl From<Wgs84Point> for Point {
+    fn from(point: Wgs84Point) -> Self {
+        wgs84_to_rd(point).into()
+    }
+}
+
+impl From<MillimeterPoint> for RdPoint {
+    fn from(point: MillimeterPoint) -> Self {
+        point.to_rd()
+    }
+}
+
+impl From<MillimeterPoint> for Wgs84Point {
+    fn from(point: MillimeterPoint) -> Self {
+        rd_to_wgs84(point.to_rd())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn distance(a: RdPoint, b: RdPoint) -> f64 {
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+    }
+
+    #[test]
+    fn origin() {
+        assert_eq!(WGS84_ORIGIN, rd_to_wgs84(RD_ORIGIN));
+        assert_eq!(RD_ORIGIN, wgs84_to_rd(WGS84_ORIGIN));
+    }
+
+    #[test]
+    fn scale() {
+        // one kilometer north is about 1/111.25 degree of latitude at these latitudes,
+        // one kilometer east is about 1/68.4 degree of longitude
+        let a = rd_to_wgs84(RdPoint::new(155_000.0, 463_000.0));
+        let b = rd_to_wgs84(RdPoint::new(155_000.0, 464_000.0));
+        let c = rd_to_wgs84(RdPoint::new(156_000.0, 463_000.0));
+
+        assert!(((b.lat - a.lat) * 111.25 - 1.0).abs() < 0.005);
+        assert!(((c.lon - a.lon) * 68.4 - 1.0).abs() < 0.005);
+    }
+
+    #[test]
+    fn reference_point() {
+        // the Westertoren in Amsterdam
+        let rd = RdPoint::new(120_700.723, 487_525.501);
+        let wgs84 = rd_to_wgs84(rd);
+
+        assert!((wgs84.lat - 52.374_532_53).abs() < 1e-6, "{:?}", wgs84);
+        assert!((wgs84.lon - 4.883_525_59).abs() < 1e-6, "{:?}", wgs84);
+
+        let back = wgs84_to_rd(Wgs84Point::new(4.883_525_59, 52.374_532_53));
+        assert!(distance(rd, back) < 0.1, "{:?}", back);
+    }
+
+    #[test]
+    fn round_trip() {
+        for x in (20_000..=280_000).step_by(20_000) {
+            for y in (310_000..=610_000).step_by(20_000) {
+                let rd = RdPoint::new(x as f64, y as f64);
+                let back = wgs84_to_rd(rd_to_wgs84(rd));
+
+                assert!(distance(rd, back) < 1.0, "{:?} -> {:?}", rd, back);
+            }
+        }
+    }
+}
@@ -0,0 +1,905 @@
+// Load the points into PostGIS
+//
+// The points are copied into a fresh staging table, in the binary COPY format with EWKB
+// geometries, and indexed there. Only then is the staging table swapped in for the real one, with
+// a drop and a rename in a single transaction. So during the (monthly) refresh, consumers keep
+// seeing the previous table, and never a half-loaded or unindexed one.
+//
+// The connection is configured like libpq does it: with a connection string, and/or the `PG*`
+// environment variables. The postgres crate only knows the sslmodes `disable`, `prefer` and
+// `require`, so the sslmode is taken out of the connection string before the crate parses it, and
+// `Connection::connect` implements the other modes of libpq on top of those.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use postgres::config::SslMode;
+use postgres::{Client, Config, GenericClient, NoTls};
+
+use crate::crs::{Crs, RdPoint};
+use crate::error::Error;
+use crate::postcode::Postcode;
+
+/// A row of the table: a postcode, and a point in the CRS of the target
+pub type Row = (Postcode, f64, f64);
+
+/// The signature, flags and (empty) header extension that start every binary COPY
+const COPY_HEADER: &[u8] = b"PGCOPY\n\xff\r\n\0\0\0\0\0\0\0\0\0";
+
+/// The sslmode of libpq
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsMode {
+    /// Never use TLS
+    Disable,
+    /// Connect without TLS, and only try TLS when that fails
+    Allow,
+    /// Try TLS first, and connect without it when that fails
+    Prefer,
+    /// Only connect with TLS, without verifying the certificate
+    Require,
+    /// Only connect with TLS, and verify the certificate but not the host name
+    VerifyCa,
+    /// Only connect with TLS, and verify the certificate and the host name
+    VerifyFull,
+}
+
+impl std::str::FromStr for TlsMode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disable" => Ok(TlsMode::Disable),
+            "allow" => Ok(TlsMode::Allow),
+            "prefer" => Ok(TlsMode::Prefer),
+            "require" => Ok(TlsMode::Require),
+            "verify-ca" => Ok(TlsMode::VerifyCa),
+            "verify-full" => Ok(TlsMode::VerifyFull),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parse a libpq connection string (`key=value` pairs or a `postgresql://` URL), and take out its
+/// sslmode
+pub fn parse_connection_string(string: &str) -> Result<(Config, Option<TlsMode>), Error> {
+    let (rest, mode) = if string.starts_with("postgresql://") || string.starts_with("postgres://") {
+        take_url_ssl_mode(string)
+    } else {
+        take_ssl_mode(string)
+    };
+
+    let mode = match mode {
+        None => None,
+        Some(mode) => Some(mode.parse().map_err(|_| invalid_input("sslmode", &mode))?),
+    };
+
+    Ok((rest.parse()?, mode))
+}
+
+/// Split the `sslmode` parameter off the query of a URL
+fn take_url_ssl_mode(url: &str) -> (String, Option<String>) {
+    let (base, query) = match url.split_once('?') {
+        None => return (url.to_string(), None),
+        Some(split) => split,
+    };
+
+    let mut mode = None;
+    let mut parameters = Vec::new();
+
+    for parameter in query.split('&') {
+        match parameter.strip_prefix("sslmode=") {
+            Some(value) => mode = Some(value.to_string()),
+            None => parameters.push(parameter),
+        }
+    }
+
+    if parameters.is_empty() {
+        (base.to_string(), mode)
+    } else {
+        (format!("{}?{}", base, parameters.join("&")), mode)
+    }
+}
+
+/// Split the `sslmode` pair off `key=value` pairs. Values are either quoted with `'` (with `\`
+/// escapes), or end at whitespace.
+fn take_ssl_mode(string: &str) -> (String, Option<String>) {
+    let bytes = string.as_bytes();
+    let skip_whitespace = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+
+    let mut mode = None;
+    let mut rest = String::with_capacity(string.len());
+    let mut copied = 0;
+    let mut i = skip_whitespace(0);
+
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && bytes[i] != b'=' && !bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        let key = &string[start..i];
+
+        i = skip_whitespace(i);
+        if bytes.get(i) != Some(&b'=') {
+            // malformed, leave it to the postgres crate to report
+            break;
+        }
+        i = skip_whitespace(i + 1);
+
+        let mut value = String::new();
+        if bytes.get(i) == Some(&b'\'') {
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'\'' {
+                if bytes[i] == b'\\' && i + 1 < bytes.len() {
+                    i += 1;
+                }
+                let c = string[i..].chars().next().unwrap();
+                value.push(c);
+                i += c.len_utf8();
+            }
+            i += 1;
+        } else {
+            let value_start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            value.push_str(&string[value_start..i]);
+        }
+        let end = i.min(bytes.len());
+
+        i = skip_whitespace(end);
+
+        if key == "sslmode" {
+            rest.push_str(&string[copied..start]);
+            copied = i;
+            mode = Some(value);
+        }
+    }
+
+    rest.push_str(&string[copied..]);
+
+    (rest, mode)
+}
+
+fn invalid_input(name: &str, value: &str) -> Error {
+    let message = format!("invalid {}: {:?}", name, value);
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        message,
+    ))
+}
+
+/// How to connect to the database
+#[derive(Debug, Clone)]
+pub struct Connection {
+    /// The connection parameters. Its sslmode is ignored, `tls` is used instead.
+    pub config: Config,
+    pub tls: TlsMode,
+    /// An extra root certificate (PEM) to trust, for servers with a private CA
+    pub root_certificate: Option<PathBuf>,
+}
+
+impl Connection {
+    /// Fill in what `config` leaves out from the environment: `PGHOST`, `PGPORT`, `PGUSER`,
+    /// `PGPASSWORD`, `PGDATABASE`, `PGSSLMODE` and `PGSSLROOTCERT`
+    ///
+    /// `config` and `tls` usually come from `parse_connection_string`. Without a `tls`, the
+    /// sslmode is `PGSSLMODE`, or `prefer`. `var` looks up an environment variable, normally with
+    /// `std::env::var`.
+    pub fn new<F>(mut config: Config, tls: Option<TlsMode>, var: F) -> Result<Self, Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let invalid = invalid_input;
+
+        if config.get_hosts().is_empty() {
+            if let Some(host) = var("PGHOST") {
+                config.host(&host);
+            }
+        }
+
+        if config.get_ports().is_empty() {
+            if let Some(port) = var("PGPORT") {
+                config.port(port.parse().map_err(|_| invalid("PGPORT", &port))?);
+            }
+        }
+
+        if config.get_user().is_none() {
+            if let Some(user) = var("PGUSER") {
+                config.user(&user);
+            }
+        }
+
+        if config.get_password().is_none() {
+            if let Some(password) = var("PGPASSWORD") {
+                config.password(&password);
+            }
+        }
+
+        if config.get_dbname().is_none() {
+            if let Some(dbname) = var("PGDATABASE") {
+                config.dbname(&dbname);
+            }
+        }
+
+        let tls = match (tls, var("PGSSLMODE")) {
+            (Some(tls), _) => tls,
+            (None, Some(mode)) => mode.parse().map_err(|_| invalid("PGSSLMODE", &mode))?,
+            (None, None) => TlsMode::Prefer,
+        };
+
+        Ok(Self {
+            config,
+            tls,
+            root_certificate: var("PGSSLROOTCERT").map(PathBuf::from),
+        })
+    }
+
+    /// Connect, with or without TLS as the sslmode says
+    ///
+    /// With `allow`, `prefer` and `require`, TLS is only used to encrypt the connection (like
+    /// libpq does), so the certificate is not checked. With `verify-ca` and `verify-full`, the
+    /// server certificate must be signed by a system root or by `root_certificate`, and with
+    /// `verify-full` it must also match the host name.
+    pub fn connect(&self) -> Result<Client, Error> {
+        let mut config = self.config.clone();
+
+        match self.tls {
+            TlsMode::Disable => Ok(config.ssl_mode(SslMode::Disable).connect(NoTls)?),
+            TlsMode::Allow => match config.ssl_mode(SslMode::Disable).connect(NoTls) {
+                Ok(client) => Ok(client),
+                Err(_) => {
+                    let connector = self.connector()?;
+                    Ok(config.ssl_mode(SslMode::Require).connect(connector)?)
+                }
+            },
+            TlsMode::Prefer => {
+                // a server without TLS is handled by the postgres crate, a failed handshake here
+                match config.ssl_mode(SslMode::Prefer).connect(self.connector()?) {
+                    Ok(client) => Ok(client),
+                    Err(_) => Ok(config.ssl_mode(SslMode::Disable).connect(NoTls)?),
+                }
+            }
+            TlsMode::Require | TlsMode::VerifyCa | TlsMode::VerifyFull => {
+                let connector = self.connector()?;
+                Ok(config.ssl_mode(SslMode::Require).connect(connector)?)
+            }
+        }
+    }
+
+    /// A TLS connector that verifies as much as `self.tls` asks for
+    fn connector(&self) -> Result<postgres_native_tls::MakeTlsConnector, Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+
+        if let Some(path) = &self.root_certificate {
+            builder.add_root_certificate(read_certificate(path)?);
+        }
+
+        match self.tls {
+            TlsMode::VerifyFull => (),
+            TlsMode::VerifyCa => {
+                builder.danger_accept_invalid_hostnames(true);
+            }
+            _ => {
+                builder.danger_accept_invalid_certs(true);
+                builder.danger_accept_invalid_hostnames(true);
+            }
+        }
+
+        Ok(postgres_native_tls::MakeTlsConnector::new(builder.build()?))
+    }
+}
+
+fn read_certificate(path: &Path) -> Result<native_tls::Certificate, Error> {
+    let pem = std::fs::read(path)?;
+
+    Ok(native_tls::Certificate::from_pem(&pem)?)
+}
+
+/// The table that the points are loaded into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub schema: String,
+    pub table: String,
+    /// The CRS of the stored points; also determines the SRID of the geometry column
+    pub crs: Crs,
+}
+
+impl Target {
+    /// `adressen_<srid>` in the public schema
+    pub fn new(crs: Crs) -> Self {
+        Self {
+            schema: "public".to_string(),
+            table: format!("adressen_{}", crs.epsg()),
+            crs,
+        }
+    }
+
+    /// `name` in the schema of the target, quoted
+    fn qualified(&self, name: &str) -> String {
+        format!(
+            "{}.{}",
+            quote_identifier(&self.schema),
+            quote_identifier(name)
+        )
+    }
+}
+
+/// Quote an identifier, so that any name (even with capitals or quotes) can be used
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// A point as little endian extended WKB, which embeds the SRID
+fn ewkb_point(srid: u32, x: f64, y: f64) -> [u8; 25] {
+    /// set in the geometry type when an SRID follows it
+    const SRID_FLAG: u32 = 0x2000_0000;
+
+    let mut ewkb = [0; 25];
+
+    // little endian, geometry type 1 (Point)
+    ewkb[0] = 1;
+    ewkb[1..5].copy_from_slice(&(1 | SRID_FLAG).to_le_bytes());
+    ewkb[5..9].copy_from_slice(&srid.to_le_bytes());
+    ewkb[9..17].copy_from_slice(&x.to_le_bytes());
+    ewkb[17..25].copy_from_slice(&y.to_le_bytes());
+
+    ewkb
+}
+
+/// The point of an EWKB geometry, or `None` if it is not a point
+fn parse_ewkb_point(ewkb: &[u8]) -> Option<(f64, f64)> {
+    let little_endian = match ewkb.first()? {
+        0 => false,
+        1 => true,
+        _ => return None,
+    };
+
+    let u32_at = |offset: usize| {
+        let bytes = ewkb.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+    let f64_at = |offset: usize| {
+        let bytes = ewkb.get(offset..offset + 8)?.try_into().ok()?;
+        Some(if little_endian {
+            f64::from_le_bytes(bytes)
+        } else {
+            f64::from_be_bytes(bytes)
+        })
+    };
+
+    // the low bits are the geometry type, the high bits are flags; only the SRID flag is expected
+    let geometry_type = u32_at(1)?;
+    let start = match geometry_type {
+        1 => 5,
+        0x2000_0001 => 9,
+        _ => return None,
+    };
+
+    Some((f64_at(start)?, f64_at(start + 8)?))
+}
+
+/// Write one row of the binary COPY format: the number of fields, and then every field as its
+/// length followed by its value. All integers are big endian.
+fn write_copy_row<W: Write>(writer: &mut W, fields: &[&[u8]]) -> std::io::Result<()> {
+    writer.write_all(&(fields.len() as i16).to_be_bytes())?;
+
+    for field in fields {
+        writer.write_all(&(field.len() as i32).to_be_bytes())?;
+        writer.write_all(field)?;
+    }
+
+    Ok(())
+}
+
+/// Read a `(point, postcode)` row of the binary COPY format, or `None` after the last row
+fn read_copy_row<R: Read>(reader: &mut R, buf: &mut Vec<u8>) -> std::io::Result<Option<Row>> {
+    let invalid = |message: &str| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+    let read_field = |reader: &mut R, buf: &mut Vec<u8>| {
+        let mut length = [0; 4];
+        reader.read_exact(&mut length)?;
+
+        let length = usize::try_from(i32::from_be_bytes(length))
+            .map_err(|_| invalid("unexpected NULL in COPY row"))?;
+
+        buf.resize(length, 0);
+        reader.read_exact(buf)
+    };
+
+    let mut count = [0; 2];
+    reader.read_exact(&mut count)?;
+
+    match i16::from_be_bytes(count) {
+        -1 => return Ok(None),
+        2 => (),
+        _ => return Err(invalid("expected 2 fields in COPY row")),
+    }
+
+    read_field(reader, buf)?;
+    let (x, y) = parse_ewkb_point(buf).ok_or_else(|| invalid("expected a point"))?;
+
+    read_field(reader, buf)?;
+    let postcode = std::str::from_utf8(buf)
+        .ok()
+        .and_then(|string| Postcode::try_from(string).ok())
+        .ok_or_else(|| invalid("expected a postcode"))?;
+
+    Ok(Some((postcode, x, y)))
+}
+
+/// Copy rows with the given (full) COPY query, that must list the `point` and `postcode` columns,
+/// and a third (integer) column when the rows have a count
+fn copy_rows<C, I>(client: &mut C, query: &str, srid: u32, rows: I) -> Result<u64, Error>
+where
+    C: GenericClient,
+    I: Iterator<Item = (Row, Option<i32>)>,
+{
+    let mut writer = client.copy_in(query)?;
+
+    writer.write_all(COPY_HEADER)?;
+    for ((postcode, x, y), count) in rows {
+        let point = ewkb_point(srid, x, y);
+        let postcode = postcode.to_string();
+
+        match count {
+            None => write_copy_row(&mut writer, &[&point, postcode.as_bytes()])?,
+            Some(count) => {
+                let count = count.to_be_bytes();
+                write_copy_row(&mut writer, &[&point, postcode.as_bytes(), &count])?
+            }
+        }
+    }
+    // the trailer: a row with -1 fields
+    writer.write_all(&(-1i16).to_be_bytes())?;
+
+    Ok(writer.finish()?)
+}
+
+/// The rows for points, in the given CRS
+pub fn project<I>(crs: Crs, points: I) -> impl Iterator<Item = Row>
+where
+    I: Iterator<Item = (Postcode, RdPoint)>,
+{
+    points.map(move |(postcode, point)| {
+        let (x, y) = crs.project(point);
+        (postcode, x, y)
+    })
+}
+
+/// Replace the target table by a table with the given rows, and a GiST index on them
+///
+/// The schema must exist. Returns the number of rows that were loaded.
+pub fn load<I>(client: &mut Client, target: &Target, rows: I) -> Result<u64, Error>
+where
+    I: Iterator<Item = Row>,
+{
+    let staging_name = format!("{}_staging", target.table);
+    let staging = target.qualified(&staging_name);
+    let table = target.qualified(&target.table);
+
+    // left over from a load that failed halfway
+    client.batch_execute(&format!("DROP TABLE IF EXISTS {}", staging))?;
+
+    client.batch_execute(&format!(
+        "CREATE TABLE {} (
+            point geometry(Point, {}) NOT NULL,
+            postcode character varying(6) NOT NULL
+        )",
+        staging,
+        target.crs.epsg()
+    ))?;
+
+    println!("Inserting data into {}", staging);
+
+    let query = format!(
+        "COPY {} (point, postcode) FROM STDIN (FORMAT binary)",
+        staging
+    );
+    let rows_written = copy_rows(
+        client,
+        &query,
+        target.crs.epsg(),
+        rows.map(|row| (row, None)),
+    )?;
+
+    println!("Done inserting data, inserted {} rows", rows_written);
+
+    client.batch_execute(&format!(
+        "CREATE INDEX {index} ON {staging} USING gist (point);
+        ANALYZE {staging};",
+        index = quote_identifier(&format!("{}_point", staging_name)),
+        staging = staging
+    ))?;
+
+    let mut transaction = client.transaction()?;
+    transaction.batch_execute(&format!(
+        "DROP TABLE IF EXISTS {table};
+        ALTER TABLE {staging} RENAME TO {table_name};
+        ALTER INDEX {staging_index} RENAME TO {table_index};",
+        table = table,
+        staging = staging,
+        table_name = quote_identifier(&target.table),
+        staging_index = target.qualified(&format!("{}_point", staging_name)),
+        table_index = quote_identifier(&format!("{}_point", target.table)),
+    ))?;
+    transaction.commit()?;
+
+    println!("Swapped {} in for {}", staging, table);
+
+    Ok(rows_written)
+}
+
+/// All rows of the target table
+pub fn read_rows(client: &mut Client, target: &Target) -> Result<Vec<Row>, Error> {
+    let query = format!(
+        "COPY {} (point, postcode) TO STDOUT (FORMAT binary)",
+        target.qualified(&target.table)
+    );
+    let mut reader = std::io::BufReader::new(client.copy_out(&query)?);
+
+    let mut header = [0; COPY_HEADER.len()];
+    reader.read_exact(&mut header)?;
+    if header != COPY_HEADER {
+        let message = "unexpected binary COPY header";
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, message).into());
+    }
+
+    let mut rows = Vec::new();
+    let mut buf = Vec::new();
+    while let Some(row) = read_copy_row(&mut reader, &mut buf)? {
+        rows.push(row);
+    }
+
+    Ok(rows)
+}
+
+/// The difference between the rows of a table and the rows that it should have
+#[derive(Debug, Default, PartialEq)]
+pub struct Changes {
+    /// Sorted by postcode. A row that must be inserted several times occurs several times.
+    pub inserted: Vec<Row>,
+    /// Sorted by postcode. A row that must be deleted several times occurs several times.
+    pub deleted: Vec<Row>,
+}
+
+impl Changes {
+    /// Compare `old` and `new` as multisets. Points are compared exactly, so only when they are
+    /// the same `f64`s.
+    pub fn between(mut old: Vec<Row>, mut new: Vec<Row>) -> Self {
+        use rayon::prelude::*;
+        use std::cmp::Ordering;
+
+        let key = |(postcode, x, y): &Row| (postcode.as_u32(), x.to_bits(), y.to_bits());
+
+        rayon::join(
+            || old.par_sort_unstable_by_key(key),
+            || new.par_sort_unstable_by_key(key),
+        );
+
+        let mut changes = Changes::default();
+
+        let mut old = old.into_iter().peekable();
+        let mut new = new.into_iter().peekable();
+
+        loop {
+            let order = match (old.peek(), new.peek()) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(a), Some(b)) => key(a).cmp(&key(b)),
+            };
+
+            match order {
+                Ordering::Less => changes.deleted.extend(old.next()),
+                Ordering::Greater => changes.inserted.extend(new.next()),
+                Ordering::Equal => {
+                    old.next();
+                    new.next();
+                }
+            }
+        }
+
+        changes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inserted.is_empty() && self.deleted.is_empty()
+    }
+
+    /// The number of points that moved within their postcode: a deleted and an inserted row with
+    /// the same postcode are counted as one moved point
+    pub fn moved(&self) -> usize {
+        let mut inserted = self.inserted.iter().map(|row| row.0).peekable();
+        let mut moved = 0;
+
+        for postcode in self.deleted.iter().map(|row| row.0) {
+            while inserted.next_if(|other| *other < postcode).is_some() {}
+
+            if inserted.next_if_eq(&postcode).is_some() {
+                moved += 1;
+            }
+        }
+
+        moved
+    }
+
+    /// `deleted`, with every distinct row once, and the number of times it occurs
+    fn deleted_counts(&self) -> impl Iterator<Item = (Row, i32)> + '_ {
+        let mut deleted = self.deleted.iter().copied().peekable();
+
+        std::iter::from_fn(move || {
+            let row = deleted.next()?;
+            let same = |other: &Row| other.0 == row.0 && other.1 == row.1 && other.2 == row.2;
+
+            let mut count = 1;
+            while deleted.next_if(same).is_some() {
+                count += 1;
+            }
+
+            Some((row, count))
+        })
+    }
+}
+
+/// Apply `changes` to the target table, in a single transaction
+///
+/// Fails (and changes nothing) when the rows to delete are not all in the table: then the table
+/// does not have the contents that the changes were computed from.
+pub fn update(client: &mut Client, target: &Target, changes: &Changes) -> Result<(), Error> {
+    let table = target.qualified(&target.table);
+    let srid = target.crs.epsg();
+
+    let mut transaction = client.transaction()?;
+
+    transaction.batch_execute(&format!(
+        "CREATE TEMPORARY TABLE deleted (
+            id serial,
+            point geometry(Point, {srid}) NOT NULL,
+            postcode character varying(6) NOT NULL,
+            count integer NOT NULL
+        ) ON COMMIT DROP;
+        CREATE TEMPORARY TABLE inserted (
+            point geometry(Point, {srid}) NOT NULL,
+            postcode character varying(6) NOT NULL
+        ) ON COMMIT DROP;",
+        srid = srid
+    ))?;
+
+    let query = "COPY deleted (point, postcode, count) FROM STDIN (FORMAT binary)";
+    let deleted_counts = changes
+        .deleted_counts()
+        .map(|(row, count)| (row, Some(count)));
+    copy_rows(&mut transaction, query, srid, deleted_counts)?;
+
+    let query = "COPY inserted (point, postcode) FROM STDIN (FORMAT binary)";
+    let inserted = changes.inserted.iter().map(|row| (*row, None));
+    copy_rows(&mut transaction, query, srid, inserted)?;
+
+    // a row that occurs `count` times in `deleted` deletes that many of the equal rows in the
+    // table. `~=` (same bounding box) can use the GiST index, `ST_Equals` makes it exact
+    let deleted = transaction.execute(
+        format!(
+            "DELETE FROM {table} WHERE ctid IN (
+                SELECT ctid FROM (
+                    SELECT t.ctid, d.count,
+                        row_number() OVER (PARTITION BY d.id ORDER BY t.ctid) AS n
+                    FROM deleted d
+                    JOIN {table} t
+                        ON t.point ~= d.point
+                        AND ST_Equals(t.point, d.point)
+                        AND t.postcode = d.postcode
+                ) matches
+                WHERE n <= count
+            )",
+            table = table
+        )
+        .as_str(),
+        &[],
+    )?;
+
+    if deleted != changes.deleted.len() as u64 {
+        let message = format!(
+            "{} has changed: only {} of the {} rows to delete were found",
+            table,
+            deleted,
+            changes.deleted.len()
+        );
+
+        // dropping the transaction rolls it back
+        return Err(std::io::Error::other(message).into());
+    }
+
+    let inserted = transaction.execute(
+        format!(
+            "INSERT INTO {} (point, postcode) SELECT point, postcode FROM inserted",
+            table
+        )
+        .as_str(),
+        &[],
+    )?;
+
+    transaction.commit()?;
+
+    println!(
+        "Updated {}: deleted {} rows, inserted {} rows ({} moved within their postcode)",
+        table,
+        deleted,
+        inserted,
+        changes.moved()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn copy_row() {
+        let postcode = Postcode::try_from("1012BS").unwrap();
+        let point = ewkb_point(28992, 121_000.5, 487_000.25);
+
+        let mut row = Vec::new();
+        write_copy_row(&mut row, &[&point, b"1012BS"]).unwrap();
+
+        assert_eq!(2 + 4 + 25 + 4 + 6, row.len());
+        assert_eq!(&[0, 2, 0, 0, 0, 25], &row[..6]);
+
+        // the SRID flag is the most significant (little endian: last) byte of the type
+        let ewkb = &row[6..31];
+        assert_eq!(&[1, 1, 0, 0, 0x20], &ewkb[..5]);
+        assert_eq!(28992u32.to_le_bytes(), ewkb[5..9]);
+        assert_eq!(121_000.5f64.to_le_bytes(), ewkb[9..17]);
+
+        assert_eq!(&[0, 0, 0, 6], &row[31..35]);
+        assert_eq!(b"1012BS", &row[35..]);
+
+        assert_eq!(19, COPY_HEADER.len());
+
+        // the trailer ends the rows
+        row.extend_from_slice(&(-1i16).to_be_bytes());
+
+        let mut reader = row.as_slice();
+        let mut buf = Vec::new();
+        let expected = (postcode, 121_000.5, 487_000.25);
+        assert_eq!(
+            Some(expected),
+            read_copy_row(&mut reader, &mut buf).unwrap()
+        );
+        assert_eq!(None, read_copy_row(&mut reader, &mut buf).unwrap());
+
+        // big endian, and without an SRID
+        let mut ewkb = vec![0, 0, 0, 0, 1];
+        ewkb.extend_from_slice(&5.0f64.to_be_bytes());
+        ewkb.extend_from_slice(&52.0f64.to_be_bytes());
+        assert_eq!(Some((5.0, 52.0)), parse_ewkb_point(&ewkb));
+        assert_eq!(None, parse_ewkb_point(&ewkb[..20]));
+    }
+
+    #[test]
+    fn changes() {
+        let postcode = |input| Postcode::try_from(input).unwrap();
+        let (a, b, c) = (postcode("1012BS"), postcode("1012BT"), postcode("1012BU"));
+
+        let old = vec![(a, 1.0, 1.0), (a, 1.0, 1.0), (b, 2.0, 2.0), (c, 3.0, 3.0)];
+        let new = vec![(c, 3.0, 3.0), (a, 1.0, 1.0), (b, 2.5, 2.0), (b, 4.0, 4.0)];
+
+        let changes = Changes::between(old.clone(), new);
+        assert_eq!(vec![(a, 1.0, 1.0), (b, 2.0, 2.0)], changes.deleted);
+        assert_eq!(vec![(b, 2.5, 2.0), (b, 4.0, 4.0)], changes.inserted);
+        assert_eq!(1, changes.moved());
+
+        let counts: Vec<_> = changes.deleted_counts().collect();
+        assert_eq!(vec![((a, 1.0, 1.0), 1), ((b, 2.0, 2.0), 1)], counts);
+
+        let changes = Changes::between(old.clone(), Vec::new());
+        let counts: Vec<_> = changes.deleted_counts().collect();
+        assert_eq!(((a, 1.0, 1.0), 2), counts[0]);
+        assert_eq!(3, counts.len());
+
+        assert!(Changes::between(old.clone(), old).is_empty());
+    }
+
+    #[test]
+    fn connection() {
+        let environment = |name: &str| match name {
+            "PGHOST" => Some("db.example.com".to_string()),
+            "PGUSER" => Some("loader".to_string()),
+            "PGPORT" => Some("6543".to_string()),
+            "PGSSLMODE" => Some("require".to_string()),
+            "PGSSLROOTCERT" => Some("/etc/ssl/ca.pem".to_string()),
+            _ => None,
+        };
+
+        let connection = Connection::new(Config::new(), None, environment).unwrap();
+        assert_eq!(Some("loader"), connection.config.get_user());
+        assert_eq!(&[6543], connection.config.get_ports());
+        assert_eq!(TlsMode::Require, connection.tls);
+        assert_eq!(
+            Some(Path::new("/etc/ssl/ca.pem")),
+            connection.root_certificate.as_deref()
+        );
+
+        // the connection string takes precedence over the environment
+        let string = "host=localhost user=tgbag dbname=bagextract sslmode=disable";
+        let (config, tls) = parse_connection_string(string).unwrap();
+        let connection = Connection::new(config, tls, environment).unwrap();
+        assert_eq!(Some("tgbag"), connection.config.get_user());
+        assert_eq!(Some("bagextract"), connection.config.get_dbname());
+        assert_eq!(&[6543], connection.config.get_ports());
+        assert_eq!(TlsMode::Disable, connection.tls);
+
+        let url = "postgresql://loader@db.example.com:5433/bag?sslmode=verify-full";
+        let (config, tls) = parse_connection_string(url).unwrap();
+        let connection = Connection::new(config, tls, |_| None).unwrap();
+        assert_eq!(&[5433], connection.config.get_ports());
+        assert_eq!(TlsMode::VerifyFull, connection.tls);
+
+        let connection = Connection::new(Config::new(), None, |_| None).unwrap();
+        assert_eq!(TlsMode::Prefer, connection.tls);
+
+        let allow = |name: &str| (name == "PGSSLMODE").then(|| "allow".to_string());
+        let connection = Connection::new(Config::new(), None, allow).unwrap();
+        assert_eq!(TlsMode::Allow, connection.tls);
+
+        let invalid = |name: &str| (name == "PGPORT").then(|| "postgres".to_string());
+        assert!(Connection::new(Config::new(), None, invalid).is_err());
+        assert!(parse_connection_string("host=localhost sslmode=always").is_err());
+    }
+
+    #[test]
+    fn ssl_mode() {
+        let string = "host=localhost password='a b\\' sslmode=x' sslmode = 'verify-ca' port=5433";
+        let (rest, mode) = take_ssl_mode(string);
+        assert_eq!("host=localhost password='a b\\' sslmode=x' port=5433", rest);
+        assert_eq!(Some("verify-ca".to_string()), mode);
+
+        let (config, tls) = parse_connection_string(string).unwrap();
+        assert_eq!(Some(TlsMode::VerifyCa), tls);
+        assert_eq!(Some(&b"a b' sslmode=x"[..]), config.get_password());
+        assert_eq!(&[5433], config.get_ports());
+
+        let url = "postgresql://db.example.com/bag?sslmode=allow&application_name=bagextract";
+        let (rest, mode) = take_url_ssl_mode(url);
+        assert_eq!(
+            "postgresql://db.example.com/bag?application_name=bagextract",
+            rest
+        );
+        assert_eq!(Some("allow".to_string()), mode);
+
+        let url = "postgresql://db.example.com/bag?sslmode=require";
+        let (rest, mode) = take_url_ssl_mode(url);
+        assert_eq!("postgresql://db.example.com/bag", rest);
+        assert_eq!(Some("require".to_string()), mode);
+
+        assert_eq!(
+            ("host=localhost".to_string(), None),
+            take_ssl_mode("host=localhost")
+        );
+    }
+
+    #[test]
+    fn target() {
+        let target = Target {
+            schema: "bag".to_string(),
+            table: "Adressen \"nl\"".to_string(),
+            crs: Crs::Wgs84,
+        };
+
+        assert_eq!(
+            r#""bag"."Adressen ""nl""""#,
+            target.qualified(&target.table)
+        );
+        assert_eq!("adressen_4326", Target::new(Crs::Wgs84).table);
+    }
+}
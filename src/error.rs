@@ -0,0 +1,158 @@
+use std::fmt::Display;
+
+/// Everything that can go wrong while reading the BAG extract
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// A zip archive (or an entry in it) could not be read
+    Zip {
+        archive: String,
+        error: zip::result::ZipError,
+    },
+    /// The xml in a zip entry is malformed
+    Xml {
+        entry: String,
+        position: usize,
+        error: quick_xml::Error,
+    },
+    /// The xml is well-formed, but a value in it is not what we expect
+    InvalidValue {
+        entry: String,
+        position: usize,
+        field: &'static str,
+        text: String,
+    },
+    /// A generated index file is not what this version expects
+    InvalidIndex {
+        path: String,
+        message: String,
+    },
+    /// Writing a GeoPackage failed
+    Sqlite(rusqlite::Error),
+    /// Writing a GeoParquet file failed
+    Parquet(parquet::errors::ParquetError),
+    /// Loading the database failed
+    Postgres(postgres::Error),
+    /// Setting up TLS for the database connection failed
+    Tls(native_tls::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Io(error) => write!(f, "{}", error),
+            Error::Zip { archive, error } => write!(f, "in {}: {}", archive, error),
+            Error::Xml {
+                entry,
+                position,
+                error,
+            } => write!(f, "in {} at byte {}: {}", entry, position, error),
+            Error::InvalidValue {
+                entry,
+                position,
+                field,
+                text,
+            } => write!(
+                f,
+                "in {} at byte {}: invalid {}: {:?}",
+                entry, position, field, text
+            ),
+            Error::InvalidIndex { path, message } => write!(f, "{}: {}", path, message),
+            Error::Sqlite(error) => write!(f, "sqlite: {}", error),
+            Error::Parquet(error) => write!(f, "parquet: {}", error),
+            Error::Postgres(error) => write!(f, "postgres: {}", error),
+            Error::Tls(error) => write!(f, "tls: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            Error::Zip { error, .. } => Some(error),
+            Error::Xml { error, .. } => Some(error),
+            Error::InvalidValue { .. } => None,
+            Error::InvalidIndex { .. } => None,
+            Error::Sqlite(error) => Some(error),
+            Error::Parquet(error) => Some(error),
+            Error::Postgres(error) => Some(error),
+            Error::Tls(error) => Some(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(error: rusqlite::Error) -> Self {
+        Error::Sqlite(error)
+    }
+}
+
+impl From<parquet::errors::ParquetError> for Error {
+    fn from(error: parquet::errors::ParquetError) -> Self {
+        Error::Parquet(error)
+    }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(error: postgres::Error) -> Self {
+        Error::Postgres(error)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(error: native_tls::Error) -> Self {
+        Error::Tls(error)
+    }
+}
+
+impl Error {
+    pub(crate) fn xml(entry: &str, position: usize, error: quick_xml::Error) -> Self {
+        Error::Xml {
+            entry: entry.to_string(),
+            position,
+            error,
+        }
+    }
+
+    /// A copy of an error that occurred while re-opening an archive that was opened before
+    pub(crate) fn reopen(error: &Error) -> Self {
+        let message = format!("cannot re-open archive: {}", error);
+        Error::Io(std::io::Error::other(message))
+    }
+
+    pub(crate) fn invalid_value(
+        entry: &str,
+        position: usize,
+        field: &'static str,
+        text: &[u8],
+    ) -> Self {
+        Error::InvalidValue {
+            entry: entry.to_string(),
+            position,
+            field,
+            text: String::from_utf8_lossy(text).into_owned(),
+        }
+    }
+}
+
+/// Parse the text of an xml element, using `parse`. Any failure is reported with the given context.
+pub(crate) fn parse_text<'a, T, E>(
+    entry: &str,
+    position: usize,
+    field: &'static str,
+    text: &'a [u8],
+    parse: impl FnOnce(&'a str) -> Result<T, E>,
+) -> Result<T, Error> {
+    let invalid = || Error::invalid_value(entry, position, field, text);
+
+    let string = std::str::from_utf8(text).map_err(|_| invalid())?;
+
+    parse(string).map_err(|_| invalid())
+}
@@ -0,0 +1,259 @@
+// Write the points of the index file in formats that GIS tools can read
+//
+// All writers stream: they write one postcode (or adres) at a time, so the output is never held
+// in memory as a whole. Postcodes without points are left out.
+
+use std::io::Write;
+
+use crate::crs::{Crs, RdPoint};
+use crate::geocode::AddressEntry;
+use crate::point::Coordinates;
+use crate::postcode::Postcode;
+use crate::report::write_json_string;
+
+/// What the `export` subcommand writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A GeoJSON FeatureCollection with a MultiPoint feature per postcode
+    GeoJson,
+    /// A GeoJSON FeatureCollection with a Point feature per adres
+    GeoJsonAddresses,
+    /// `postcode,x,y` for every adres, with a header line
+    Csv,
+    /// A GeoPackage file, see `crate::gpkg`
+    GeoPackage,
+}
+
+/// Write the coordinates of `point` in `crs`, separated by a comma
+///
+/// RD New is written in millimeters, WGS84 with 7 decimals (about a centimeter).
+fn write_coordinates<W: Write>(writer: &mut W, crs: Crs, point: RdPoint) -> std::io::Result<()> {
+    let (x, y) = crs.project(point);
+
+    match crs {
+        Crs::Rd => write!(writer, "{:.3},{:.3}", x, y),
+        Crs::Wgs84 => write!(writer, "{:.7},{:.7}", x, y),
+    }
+}
+
+/// The start of a FeatureCollection. GeoJSON is WGS84 by definition, other systems are named
+/// with the (deprecated, but widely supported) `crs` member.
+fn write_feature_collection_start<W: Write>(writer: &mut W, crs: Crs) -> std::io::Result<()> {
+    write!(writer, "{{\"type\":\"FeatureCollection\",")?;
+
+    if crs != Crs::Wgs84 {
+        write!(
+            writer,
+            "\"crs\":{{\"type\":\"name\",\"properties\":{{\"name\":\"urn:ogc:def:crs:EPSG::{}\"}}}},",
+            crs.epsg()
+        )?;
+    }
+
+    writeln!(writer, "\"features\":[")
+}
+
+fn write_feature_collection_end<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "]}}")
+}
+
+/// Write a MultiPoint feature for every postcode
+pub fn write_geojson<'a, P, I, W>(points: I, crs: Crs, mut writer: W) -> std::io::Result<()>
+where
+    P: Coordinates,
+    I: Iterator<Item = (Postcode, &'a [P])>,
+    W: Write,
+{
+    write_feature_collection_start(&mut writer, crs)?;
+
+    let mut first = true;
+
+    for (postcode, points) in points.filter(|(_, points)| !points.is_empty()) {
+        if !first {
+            writeln!(writer, ",")?;
+        }
+        first = false;
+
+        write!(
+            writer,
+            "{{\"type\":\"Feature\",\"properties\":{{\"postcode\":"
+        )?;
+        write_json_string(&mut writer, &postcode.to_string())?;
+        write!(
+            writer,
+            "}},\"geometry\":{{\"type\":\"MultiPoint\",\"coordinates\":["
+        )?;
+
+        for (i, point) in points.iter().enumerate() {
+            if i > 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, "[")?;
+            write_coordinates(&mut writer, crs, point.to_rd())?;
+            write!(writer, "]")?;
+        }
+
+        write!(writer, "]}}}}")?;
+    }
+
+    if !first {
+        writeln!(writer)?;
+    }
+
+    write_feature_collection_end(&mut writer)
+}
+
+/// Write a Point feature for every adres, with its postcode and huisnummer
+pub fn write_geojson_addresses<P, W>(
+    addresses: &[AddressEntry<P>],
+    crs: Crs,
+    mut writer: W,
+) -> std::io::Result<()>
+where
+    P: Coordinates,
+    W: Write,
+{
+    write_feature_collection_start(&mut writer, crs)?;
+
+    for (i, adres) in addresses.iter().enumerate() {
+        if i > 0 {
+            writeln!(writer, ",")?;
+        }
+
+        write!(
+            writer,
+            "{{\"type\":\"Feature\",\"properties\":{{\"postcode\":"
+        )?;
+        write_json_string(&mut writer, &adres.postcode().to_string())?;
+        write!(writer, ",\"huisnummer\":")?;
+        write_json_string(&mut writer, &adres.huisnummer().to_string())?;
+        write!(
+            writer,
+            "}},\"geometry\":{{\"type\":\"Point\",\"coordinates\":["
+        )?;
+        write_coordinates(&mut writer, crs, adres.point.to_rd())?;
+        write!(writer, "]}}}}")?;
+    }
+
+    if !addresses.is_empty() {
+        writeln!(writer)?;
+    }
+
+    write_feature_collection_end(&mut writer)
+}
+
+/// Write a `postcode,x,y` line for every point. For WGS84, `x` is the longitude.
+pub fn write_csv<'a, P, I, W>(points: I, crs: Crs, mut writer: W) -> std::io::Result<()>
+where
+    P: Coordinates,
+    I: Iterator<Item = (Postcode, &'a [P])>,
+    W: Write,
+{
+    writeln!(writer, "postcode,x,y")?;
+
+    for (postcode, points) in points {
+        for point in points {
+            write!(writer, "{},", postcode)?;
+            write_coordinates(&mut writer, crs, point.to_rd())?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::adres::Huisnummer;
+    use crate::point::MillimeterPoint;
+    use crate::points::PostcodePoints;
+
+    fn postcode(input: &str) -> Postcode {
+        Postcode::try_from(input).unwrap()
+    }
+
+    fn postcode_points() -> PostcodePoints<MillimeterPoint> {
+        PostcodePoints::new(
+            vec![
+                (
+                    postcode("1012BS"),
+                    MillimeterPoint::new(121_000_001, 487_000_000),
+                ),
+                (
+                    postcode("1012BV"),
+                    MillimeterPoint::new(121_030_000, 487_040_500),
+                ),
+                (
+                    postcode("1012BS"),
+                    MillimeterPoint::new(121_000_002, 487_000_000),
+                ),
+            ]
+            .into_iter(),
+        )
+    }
+
+    fn to_string(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+        let mut output = Vec::new();
+        write(&mut output).unwrap();
+
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn csv() {
+        let points = postcode_points();
+        let output = to_string(|writer| write_csv(points.iter(), Crs::Rd, writer));
+
+        let expected = "postcode,x,y
+1012BS,121000.001,487000.000
+1012BS,121000.002,487000.000
+1012BV,121030.000,487040.500
+";
+        assert_eq!(expected, output);
+
+        let output = to_string(|writer| write_csv(points.iter(), Crs::Wgs84, writer));
+        assert!(output.lines().nth(1).unwrap().starts_with("1012BS,4.8"));
+    }
+
+    #[test]
+    fn geojson() {
+        let points = postcode_points();
+        let output = to_string(|writer| write_geojson(points.iter(), Crs::Rd, writer));
+
+        let expected = r#"{"type":"FeatureCollection","crs":{"type":"name","properties":{"name":"urn:ogc:def:crs:EPSG::28992"}},"features":[
+{"type":"Feature","properties":{"postcode":"1012BS"},"geometry":{"type":"MultiPoint","coordinates":[[121000.001,487000.000],[121000.002,487000.000]]}},
+{"type":"Feature","properties":{"postcode":"1012BV"},"geometry":{"type":"MultiPoint","coordinates":[[121030.000,487040.500]]}}
+]}
+"#;
+        assert_eq!(expected, output);
+
+        let empty = PostcodePoints::<MillimeterPoint>::new(std::iter::empty());
+        let output = to_string(|writer| write_geojson(empty.iter(), Crs::Wgs84, writer));
+        assert_eq!(
+            "{\"type\":\"FeatureCollection\",\"features\":[\n]}\n",
+            output
+        );
+    }
+
+    #[test]
+    fn geojson_addresses() {
+        let huisnummer = Huisnummer {
+            nummer: 12,
+            letter: Some(b'A'),
+            toevoeging: None,
+        };
+        let addresses = [AddressEntry::new(
+            postcode("1012BS"),
+            huisnummer,
+            MillimeterPoint::new(121_000_001, 487_000_000),
+        )];
+
+        let output = to_string(|writer| write_geojson_addresses(&addresses, Crs::Rd, writer));
+
+        let expected = r#"{"type":"FeatureCollection","crs":{"type":"name","properties":{"name":"urn:ogc:def:crs:EPSG::28992"}},"features":[
+{"type":"Feature","properties":{"postcode":"1012BS","huisnummer":"12A"},"geometry":{"type":"Point","coordinates":[121000.001,487000.000]}}
+]}
+"#;
+        assert_eq!(expected, output);
+    }
+}
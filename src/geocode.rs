@@ -0,0 +1,263 @@
+// Forward geocoding: find the location of an adres given its postcode and house number
+//
+// All addresses are stored in one array, sorted by postcode and house number. A lookup is a
+// binary search in that array, so no parsing or building of a map is needed at startup.
+//
+// Reverse geocoding: find the adres that is closest to a location
+//
+// A grid (see `crate::grid`) over the individual addresses points into the addresses array.
+
+use crate::adres::{Adres, Huisnummer, Toevoeging};
+use crate::error::Error;
+use crate::grid::{Grid, Located};
+use crate::index_file::{IndexFile, IndexFileWriter};
+use crate::memory_mapped_slice::{MemoryMappedSlice, Pod};
+use crate::point::{Coordinates, Point};
+use crate::postcode::Postcode;
+
+pub const ADDRESSES_SECTION: &str = "addresses";
+pub const ADDRESS_GRID_CELLS_SECTION: &str = "address-grid-cells";
+pub const ADDRESS_GRID_ENTRIES_SECTION: &str = "address-grid-entries";
+
+/// An adres as it is stored in the addresses file
+///
+/// The fields are laid out so that there is no implicit padding. A `letter` of 0 means "no
+/// huisletter", a `toevoeging` of all zeroes means "no huisnummertoevoeging".
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+pub struct AddressEntry<P: Coordinates = Point> {
+    pub point: P,
+    postcode: u32,
+    nummer: u32,
+    toevoeging: [u8; 4],
+    letter: u8,
+    _padding: [u8; 3],
+}
+
+// the padding is explicit, so every byte is part of a field
+unsafe impl<P: Coordinates> Pod for AddressEntry<P> {}
+const _: () = assert!(std::mem::size_of::<AddressEntry>() == 24);
+
+impl<P: Coordinates> AddressEntry<P> {
+    pub fn new(postcode: Postcode, huisnummer: Huisnummer, point: P) -> Self {
+        Self {
+            point,
+            postcode: postcode.as_u32(),
+            nummer: huisnummer.nummer,
+            toevoeging: huisnummer
+                .toevoeging
+                .map(Toevoeging::to_bytes)
+                .unwrap_or_default(),
+            letter: huisnummer.letter.unwrap_or(0),
+            _padding: [0; 3],
+        }
+    }
+
+    pub fn postcode(&self) -> Postcode {
+        Postcode::from_u32(self.postcode)
+    }
+
+    pub fn huisnummer(&self) -> Huisnummer {
+        Huisnummer {
+            nummer: self.nummer,
+            letter: if self.letter == 0 {
+                None
+            } else {
+                Some(self.letter)
+            },
+            toevoeging: Toevoeging::from_bytes(self.toevoeging),
+        }
+    }
+
+    /// The key that the addresses file is sorted on
+    fn key(&self) -> (Postcode, Huisnummer) {
+        (self.postcode(), self.huisnummer())
+    }
+}
+
+/// A point in the address grid: the location of an adres and its index in the addresses file
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+pub struct AddressGridEntry<P: Coordinates = Point> {
+    pub point: P,
+    pub index: u32,
+}
+
+// `Coordinates` are two 4-byte fields, followed by a u32, so no padding
+unsafe impl<P: Coordinates> Pod for AddressGridEntry<P> {}
+const _: () = assert!(std::mem::size_of::<AddressGridEntry>() == 12);
+
+impl<P: Coordinates> Located for AddressGridEntry<P> {
+    type Coordinates = P;
+
+    fn point(&self) -> P {
+        self.point
+    }
+}
+
+pub struct Addresses<P: Coordinates = Point> {
+    entries: MemoryMappedSlice<AddressEntry<P>>,
+    grid: Grid<AddressGridEntry<P>>,
+}
+
+impl<P: Coordinates> Addresses<P> {
+    /// Load the sections that `write_sections` wrote
+    pub fn from_index_file(index_file: &IndexFile) -> Result<Self, Error> {
+        let addresses = Self {
+            entries: index_file.section(ADDRESSES_SECTION)?,
+            grid: Grid::from_sections(
+                index_file,
+                ADDRESS_GRID_CELLS_SECTION,
+                ADDRESS_GRID_ENTRIES_SECTION,
+            )?,
+        };
+
+        Ok(addresses)
+    }
+
+    pub fn write_sections(
+        writer: &mut IndexFileWriter,
+        addresses: &[Adres],
+    ) -> std::io::Result<()> {
+        let mut entries: Vec<AddressEntry<P>> = addresses
+            .iter()
+            .map(|adres| {
+                let point = P::from_rd(adres.point.to_rd());
+                AddressEntry::new(adres.postcode, adres.huisnummer, point)
+            })
+            .collect();
+
+        entries.sort_by_key(AddressEntry::key);
+
+        writer.write_section(ADDRESSES_SECTION, &entries)?;
+
+        let grid_entries = entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| AddressGridEntry {
+                point: entry.point,
+                index: index as u32,
+            });
+
+        Grid::write_sections(
+            writer,
+            ADDRESS_GRID_CELLS_SECTION,
+            ADDRESS_GRID_ENTRIES_SECTION,
+            grid_entries,
+        )
+    }
+
+    /// All addresses, sorted by postcode and house number
+    pub fn as_slice(&self) -> &[AddressEntry<P>] {
+        self.entries.as_slice()
+    }
+
+    /// The addresses with the given postcode, sorted by house number
+    pub fn with_postcode(&self, postcode: Postcode) -> &[AddressEntry<P>] {
+        let entries = self.as_slice();
+
+        let start = entries.partition_point(|entry| entry.postcode() < postcode);
+        let length = entries[start..].partition_point(|entry| entry.postcode() == postcode);
+
+        &entries[start..][..length]
+    }
+
+    pub fn get(&self, postcode: Postcode, huisnummer: Huisnummer) -> Option<&AddressEntry<P>> {
+        let entries = self.as_slice();
+
+        match entries.binary_search_by(|entry| entry.key().cmp(&(postcode, huisnummer))) {
+            Ok(index) => Some(&entries[index]),
+            Err(_) => None,
+        }
+    }
+
+    /// The location of the adres with the given postcode and house number
+    pub fn geocode(
+        &self,
+        postcode: Postcode,
+        huisnummer: u32,
+        huisletter: Option<u8>,
+        toevoeging: Option<Toevoeging>,
+    ) -> Option<P> {
+        let huisnummer = Huisnummer {
+            nummer: huisnummer,
+            letter: huisletter,
+            toevoeging,
+        };
+
+        self.get(postcode, huisnummer).map(|entry| entry.point)
+    }
+
+    /// The adres that is closest to `target`, and its distance to `target`
+    pub fn reverse_geocode(&self, target: P) -> Option<(&AddressEntry<P>, f32)> {
+        let (grid_entry, distance) = self.grid.nearest(target)?;
+
+        Some((&self.as_slice()[grid_entry.index as usize], distance))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point::MillimeterPoint;
+
+    #[test]
+    fn geocode() {
+        let path = std::env::temp_dir().join("bagextract-geocode.bin");
+
+        let postcode = |input| Postcode::try_from(input).unwrap();
+        let adres = |code, nummer, letter, (x, y)| Adres {
+            identificatie: 0,
+            verblijfsobject: 0,
+            postcode: postcode(code),
+            huisnummer: Huisnummer {
+                nummer,
+                letter,
+                toevoeging: None,
+            },
+            point: MillimeterPoint::from_meters(x, y),
+        };
+
+        let addresses = [
+            adres("1012CT", 1, None, (3.0, 3.0)),
+            adres("1012BS", 12, Some(b'A'), (2.0, 2.0)),
+            adres("1012BS", 12, None, (1.0, 1.0)),
+            adres("1012BS", 2, None, (0.0, 0.0)),
+        ];
+
+        let mut writer = IndexFileWriter::create(&path, 0, MillimeterPoint::KIND).unwrap();
+        Addresses::<MillimeterPoint>::write_sections(&mut writer, &addresses).unwrap();
+        writer.finish().unwrap();
+
+        let index_file = IndexFile::open(&path).unwrap();
+        let addresses = Addresses::<MillimeterPoint>::from_index_file(&index_file).unwrap();
+
+        assert_eq!(
+            Some(MillimeterPoint::new(1_000, 1_000)),
+            addresses.geocode(postcode("1012BS"), 12, None, None)
+        );
+        assert_eq!(
+            Some(MillimeterPoint::new(2_000, 2_000)),
+            addresses.geocode(postcode("1012BS"), 12, Some(b'A'), None)
+        );
+        assert_eq!(
+            None,
+            addresses.geocode(postcode("1012BS"), 12, Some(b'B'), None)
+        );
+        assert_eq!(None, addresses.geocode(postcode("1012BV"), 12, None, None));
+
+        let numbers: Vec<String> = addresses
+            .with_postcode(postcode("1012BS"))
+            .iter()
+            .map(|entry| entry.huisnummer().to_string())
+            .collect();
+        assert_eq!(vec!["2", "12", "12A"], numbers);
+
+        let (entry, distance) = addresses
+            .reverse_geocode(MillimeterPoint::new(1_500, 2_500))
+            .unwrap();
+        assert_eq!(postcode("1012BS"), entry.postcode());
+        assert_eq!("12A", entry.huisnummer().to_string());
+        assert_eq!(MillimeterPoint::distance(500 * 500 + 500 * 500), distance);
+    }
+}
@@ -0,0 +1,234 @@
+// Write the addresses to a GeoParquet file, for DuckDB, Spark and other analytics tools
+//
+// One row per adres: the postcode, the huisnummer, the identificaties of the Verblijfsobject and
+// the Nummeraanduiding, and the point as WKB. The rows are sorted by postcode, and a postcode is
+// never split over two row groups. So the min/max statistics of every row group cover a disjoint
+// range of postcodes, and a filter on postcode only reads the row groups that can match.
+// See https://geoparquet.org/releases/v1.0.0/
+
+use std::io::Write;
+use std::sync::Arc;
+
+use parquet::basic::Compression;
+use parquet::data_type::{ByteArray, ByteArrayType};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::SortingColumn;
+use parquet::schema::parser::parse_message_type;
+
+use crate::adres::Adres;
+use crate::crs::{wkb_point, Crs};
+use crate::error::Error;
+use crate::point::Coordinates;
+
+/// A good default for `write_geoparquet`: about 2MB of compressed data per row group
+pub const ROW_GROUP_SIZE: usize = 128 * 1024;
+
+const SCHEMA: &str = "
+message adres {
+    required binary postcode (STRING);
+    required binary huisnummer (STRING);
+    required binary verblijfsobject (STRING);
+    required binary nummeraanduiding (STRING);
+    required binary geometry;
+}
+";
+
+/// EPSG:28992 as PROJJSON. WGS84 is the default in GeoParquet, and needs no definition.
+const RD_PROJJSON: &str = r#"{"$schema":"https://proj.org/schemas/v0.7/projjson.schema.json","type":"ProjectedCRS","name":"Amersfoort / RD New","base_crs":{"name":"Amersfoort","datum":{"type":"GeodeticReferenceFrame","name":"Amersfoort","ellipsoid":{"name":"Bessel 1841","semi_major_axis":6377397.155,"inverse_flattening":299.1528128}},"coordinate_system":{"subtype":"ellipsoidal","axis":[{"name":"Geodetic latitude","abbreviation":"Lat","direction":"north","unit":"degree"},{"name":"Geodetic longitude","abbreviation":"Lon","direction":"east","unit":"degree"}]},"id":{"authority":"EPSG","code":4289}},"conversion":{"name":"RD New","method":{"name":"Oblique Stereographic","id":{"authority":"EPSG","code":9809}},"parameters":[{"name":"Latitude of natural origin","value":52.1561605555556,"unit":"degree","id":{"authority":"EPSG","code":8801}},{"name":"Longitude of natural origin","value":5.38763888888889,"unit":"degree","id":{"authority":"EPSG","code":8802}},{"name":"Scale factor at natural origin","value":0.9999079,"unit":"unity","id":{"authority":"EPSG","code":8805}},{"name":"False easting","value":155000,"unit":"metre","id":{"authority":"EPSG","code":8806}},{"name":"False northing","value":463000,"unit":"metre","id":{"authority":"EPSG","code":8807}}]},"coordinate_system":{"subtype":"Cartesian","axis":[{"name":"Easting","abbreviation":"X","direction":"east","unit":"metre"},{"name":"Northing","abbreviation":"Y","direction":"north","unit":"metre"}]},"id":{"authority":"EPSG","code":28992}}"#;
+
+/// The "geo" key of the file metadata, that makes a parquet file a GeoParquet file
+///
+/// `bbox` is `[min x, min y, max x, max y]`, or `None` when there are no points.
+fn geo_metadata(crs: Crs, bbox: Option<[f64; 4]>) -> String {
+    let mut column = String::from(r#"{"encoding":"WKB","geometry_types":["Point"]"#);
+
+    if crs == Crs::Rd {
+        column.push_str(r#","crs":"#);
+        column.push_str(RD_PROJJSON);
+    }
+
+    if let Some([min_x, min_y, max_x, max_y]) = bbox {
+        column.push_str(&format!(
+            r#","bbox":[{},{},{},{}]"#,
+            min_x, min_y, max_x, max_y
+        ));
+    }
+
+    column.push('}');
+
+    format!(
+        r#"{{"version":"1.0.0","primary_column":"geometry","columns":{{"geometry":{}}}}}"#,
+        column
+    )
+}
+
+/// BAG identificaties are 16 digits, and often start with a zero
+fn identificatie(id: u64) -> ByteArray {
+    ByteArray::from(format!("{:016}", id).into_bytes())
+}
+
+/// Write the addresses as GeoParquet, in the given CRS, with at least `row_group_size` rows per
+/// row group (except the last)
+///
+/// Returns the number of addresses that were written.
+pub fn write_geoparquet<W>(
+    addresses: &[Adres],
+    crs: Crs,
+    row_group_size: usize,
+    writer: W,
+) -> Result<u64, Error>
+where
+    W: Write + Send,
+{
+    use rayon::prelude::*;
+
+    let mut sorted: Vec<&Adres> = addresses.iter().collect();
+    sorted.par_sort_unstable_by_key(|adres| {
+        (adres.postcode, adres.huisnummer, adres.verblijfsobject)
+    });
+
+    let sorting_column = SortingColumn {
+        column_idx: 0,
+        descending: false,
+        nulls_first: false,
+    };
+
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_sorting_columns(Some(vec![sorting_column]))
+        .build();
+
+    let schema = Arc::new(parse_message_type(SCHEMA)?);
+    let mut writer = SerializedFileWriter::new(writer, schema, Arc::new(properties))?;
+
+    // in the output CRS, so in degrees for WGS84
+    let mut bbox: Option<[f64; 4]> = None;
+
+    let mut start = 0;
+    while start < sorted.len() {
+        let mut end = (start + row_group_size.max(1)).min(sorted.len());
+
+        // never split a postcode over two row groups
+        while end < sorted.len() && sorted[end].postcode == sorted[end - 1].postcode {
+            end += 1;
+        }
+
+        let rows = &sorted[start..end];
+        let mut columns: [Vec<ByteArray>; 5] = Default::default();
+
+        for adres in rows {
+            let (x, y) = crs.project(adres.point.to_rd());
+            bbox = Some(match bbox {
+                None => [x, y, x, y],
+                Some([min_x, min_y, max_x, max_y]) => {
+                    [min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)]
+                }
+            });
+
+            columns[0].push(ByteArray::from(adres.postcode.to_string().into_bytes()));
+            columns[1].push(ByteArray::from(adres.huisnummer.to_string().into_bytes()));
+            columns[2].push(identificatie(adres.verblijfsobject));
+            columns[3].push(identificatie(adres.identificatie));
+            columns[4].push(ByteArray::from(wkb_point(x, y).to_vec()));
+        }
+
+        let mut row_group = writer.next_row_group()?;
+
+        for values in &columns {
+            if let Some(mut column) = row_group.next_column()? {
+                column
+                    .typed::<ByteArrayType>()
+                    .write_batch(values, None, None)?;
+                column.close()?;
+            }
+        }
+
+        row_group.close()?;
+
+        start = end;
+    }
+
+    let metadata = geo_metadata(crs, bbox);
+    writer.append_key_value_metadata(KeyValue::new("geo".to_string(), metadata));
+    writer.close()?;
+
+    Ok(sorted.len() as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::adres::Huisnummer;
+    use crate::point::MillimeterPoint;
+    use crate::postcode::Postcode;
+
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::file::statistics::Statistics;
+    use parquet::record::RowAccessor;
+
+    #[test]
+    fn row_groups() {
+        let adres = |code, nummer, id: u64| Adres {
+            identificatie: 363200000000000 + id,
+            verblijfsobject: 363010000000000 + id,
+            postcode: Postcode::try_from(code).unwrap(),
+            huisnummer: Huisnummer::new(nummer),
+            point: MillimeterPoint::new(121_000_000 + id as i32, 487_000_000),
+        };
+
+        let addresses = [
+            adres("1012BT", 1, 1),
+            adres("1012BS", 2, 2),
+            adres("1012BS", 1, 3),
+            adres("1012BS", 3, 4),
+            adres("1012BU", 1, 5),
+        ];
+
+        let path = std::env::temp_dir().join("bagextract-addresses.parquet");
+        let file = std::fs::File::create(&path).unwrap();
+        let count = write_geoparquet(&addresses, Crs::Rd, 2, file).unwrap();
+        assert_eq!(5, count);
+
+        let reader = SerializedFileReader::try_from(path.as_path()).unwrap();
+        let metadata = reader.metadata();
+
+        // 1012BS is not split: [1012BS x3], [1012BT, 1012BU]
+        let ranges: Vec<_> = metadata
+            .row_groups()
+            .iter()
+            .map(|row_group| match row_group.column(0).statistics() {
+                Some(Statistics::ByteArray(statistics)) => (
+                    statistics.min_opt().unwrap().as_utf8().unwrap().to_string(),
+                    statistics.max_opt().unwrap().as_utf8().unwrap().to_string(),
+                ),
+                other => panic!("expected byte array statistics, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                ("1012BS".to_string(), "1012BS".to_string()),
+                ("1012BT".to_string(), "1012BU".to_string()),
+            ],
+            ranges
+        );
+
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!("1012BS", row.get_string(0).unwrap());
+        assert_eq!("1", row.get_string(1).unwrap());
+        assert_eq!("0363010000000003", row.get_string(2).unwrap());
+        assert_eq!("0363200000000003", row.get_string(3).unwrap());
+        assert_eq!(
+            &wkb_point(121_000.003, 487_000.0)[..],
+            row.get_bytes(4).unwrap().data()
+        );
+
+        let key_value = metadata.file_metadata().key_value_metadata().unwrap();
+        let geo = key_value.iter().find(|kv| kv.key == "geo").unwrap();
+        let geo = geo.value.as_deref().unwrap();
+        assert!(geo.starts_with(r#"{"version":"1.0.0","primary_column":"geometry""#));
+        assert!(geo.contains(r#""code":28992"#));
+        assert!(geo.contains(r#""bbox":[121000.001,487000,121000.005,487000]"#));
+    }
+}
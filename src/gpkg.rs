@@ -0,0 +1,346 @@
+// Write the addresses to a GeoPackage: one SQLite file that QGIS (and anything else that uses
+// GDAL) can open directly, without a PostGIS server
+//
+// The file contains the tables that the GeoPackage standard requires, one feature table with a
+// point, postcode and huisnummer for every adres, and an R-tree index on the points (the
+// `gpkg_rtree_index` extension). See https://www.geopackage.org/spec/
+
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+use crate::adres::Huisnummer;
+use crate::crs::{wkb_point, Crs, RdPoint};
+use crate::error::Error;
+use crate::postcode::Postcode;
+
+/// "GPKG" in ASCII
+const APPLICATION_ID: i32 = 0x4750_4B47;
+/// GeoPackage 1.2
+const USER_VERSION: i32 = 10200;
+
+const WGS84_DEFINITION: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]"#;
+
+const RD_DEFINITION: &str = r#"PROJCS["Amersfoort / RD New",GEOGCS["Amersfoort",DATUM["Amersfoort",SPHEROID["Bessel 1841",6377397.155,299.1528128,AUTHORITY["EPSG","7004"]],TOWGS84[565.2369,50.0087,465.658,-0.406857,0.350733,-1.87035,4.0812],AUTHORITY["EPSG","6289"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4289"]],PROJECTION["Oblique_Stereographic"],PARAMETER["latitude_of_origin",52.15616055555555],PARAMETER["central_meridian",5.38763888888889],PARAMETER["scale_factor",0.9999079],PARAMETER["false_easting",155000],PARAMETER["false_northing",463000],UNIT["metre",1,AUTHORITY["EPSG","9001"]],AXIS["Easting",EAST],AXIS["Northing",NORTH],AUTHORITY["EPSG","28992"]]"#;
+
+/// The tables that every GeoPackage has, and the spatial reference systems that it must define
+const SCHEMA: &str = r#"
+CREATE TABLE gpkg_spatial_ref_sys (
+    srs_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL PRIMARY KEY,
+    organization TEXT NOT NULL,
+    organization_coordsys_id INTEGER NOT NULL,
+    definition TEXT NOT NULL,
+    description TEXT
+);
+
+CREATE TABLE gpkg_contents (
+    table_name TEXT NOT NULL PRIMARY KEY,
+    data_type TEXT NOT NULL,
+    identifier TEXT UNIQUE,
+    description TEXT DEFAULT '',
+    last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+    min_x DOUBLE,
+    min_y DOUBLE,
+    max_x DOUBLE,
+    max_y DOUBLE,
+    srs_id INTEGER,
+    CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+);
+
+CREATE TABLE gpkg_geometry_columns (
+    table_name TEXT NOT NULL,
+    column_name TEXT NOT NULL,
+    geometry_type_name TEXT NOT NULL,
+    srs_id INTEGER NOT NULL,
+    z TINYINT NOT NULL,
+    m TINYINT NOT NULL,
+    CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+    CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+    CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
+);
+
+CREATE TABLE gpkg_extensions (
+    table_name TEXT,
+    column_name TEXT,
+    extension_name TEXT NOT NULL,
+    definition TEXT NOT NULL,
+    scope TEXT NOT NULL,
+    CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name)
+);
+
+INSERT INTO gpkg_spatial_ref_sys VALUES
+    ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system'),
+    ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system');
+"#;
+
+/// The triggers that keep the R-tree up to date when the feature table is edited later, e.g. in
+/// QGIS. The `ST_` functions are provided by the application that opens the file, so the
+/// triggers are only created after all rows have been inserted.
+const RTREE_TRIGGERS: &str = r#"
+CREATE TRIGGER rtree_{t}_{c}_insert AFTER INSERT ON {t}
+WHEN (new.{c} NOT NULL AND NOT ST_IsEmpty(NEW.{c}))
+BEGIN
+  INSERT OR REPLACE INTO rtree_{t}_{c} VALUES (
+    NEW.{i}, ST_MinX(NEW.{c}), ST_MaxX(NEW.{c}), ST_MinY(NEW.{c}), ST_MaxY(NEW.{c})
+  );
+END;
+
+CREATE TRIGGER rtree_{t}_{c}_update1 AFTER UPDATE OF {c} ON {t}
+WHEN OLD.{i} = NEW.{i} AND (NEW.{c} NOTNULL AND NOT ST_IsEmpty(NEW.{c}))
+BEGIN
+  INSERT OR REPLACE INTO rtree_{t}_{c} VALUES (
+    NEW.{i}, ST_MinX(NEW.{c}), ST_MaxX(NEW.{c}), ST_MinY(NEW.{c}), ST_MaxY(NEW.{c})
+  );
+END;
+
+CREATE TRIGGER rtree_{t}_{c}_update2 AFTER UPDATE OF {c} ON {t}
+WHEN OLD.{i} = NEW.{i} AND (NEW.{c} ISNULL OR ST_IsEmpty(NEW.{c}))
+BEGIN
+  DELETE FROM rtree_{t}_{c} WHERE id = OLD.{i};
+END;
+
+CREATE TRIGGER rtree_{t}_{c}_update3 AFTER UPDATE ON {t}
+WHEN OLD.{i} != NEW.{i} AND (NEW.{c} NOTNULL AND NOT ST_IsEmpty(NEW.{c}))
+BEGIN
+  DELETE FROM rtree_{t}_{c} WHERE id = OLD.{i};
+  INSERT OR REPLACE INTO rtree_{t}_{c} VALUES (
+    NEW.{i}, ST_MinX(NEW.{c}), ST_MaxX(NEW.{c}), ST_MinY(NEW.{c}), ST_MaxY(NEW.{c})
+  );
+END;
+
+CREATE TRIGGER rtree_{t}_{c}_update4 AFTER UPDATE ON {t}
+WHEN OLD.{i} != NEW.{i} AND (NEW.{c} ISNULL OR ST_IsEmpty(NEW.{c}))
+BEGIN
+  DELETE FROM rtree_{t}_{c} WHERE id IN (OLD.{i}, NEW.{i});
+END;
+
+CREATE TRIGGER rtree_{t}_{c}_delete AFTER DELETE ON {t}
+WHEN old.{c} NOT NULL
+BEGIN
+  DELETE FROM rtree_{t}_{c} WHERE id = OLD.{i};
+END;
+"#;
+
+/// The name of the feature table, e.g. `adressen_28992`
+pub fn table_name(crs: Crs) -> String {
+    format!("adressen_{}", crs.epsg())
+}
+
+/// A point in the GeoPackage binary format: a small header followed by a little-endian WKB
+/// point. Points do not need an envelope in the header.
+fn point_blob(srs_id: u32, x: f64, y: f64) -> [u8; 29] {
+    let mut blob = [0; 29];
+
+    blob[0..2].copy_from_slice(b"GP");
+    // version 0, and flags: little endian, no envelope
+    blob[2] = 0;
+    blob[3] = 0b0000_0001;
+    blob[4..8].copy_from_slice(&srs_id.to_le_bytes());
+
+    blob[8..29].copy_from_slice(&wkb_point(x, y));
+
+    blob
+}
+
+/// Write the addresses to a new GeoPackage at `path`, in the given CRS
+///
+/// An existing file at `path` is replaced. Returns the number of addresses that were written.
+pub fn write_gpkg<I>(path: &Path, crs: Crs, addresses: I) -> Result<u64, Error>
+where
+    I: Iterator<Item = (Postcode, Huisnummer, RdPoint)>,
+{
+    match std::fs::remove_file(path) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error.into()),
+        _ => (),
+    }
+
+    let mut connection = Connection::open(path)?;
+
+    connection.pragma_update(None, "application_id", APPLICATION_ID)?;
+    connection.pragma_update(None, "user_version", USER_VERSION)?;
+    // the file is written in one go, and is useless when that fails halfway anyway
+    connection.pragma_update(None, "journal_mode", "OFF")?;
+    connection.pragma_update(None, "synchronous", "OFF")?;
+
+    let table = table_name(crs);
+    let srs_id = crs.epsg();
+
+    let transaction = connection.transaction()?;
+
+    transaction.execute_batch(SCHEMA)?;
+
+    // WGS84 must always be defined, RD New only when it is used
+    let mut systems = vec![("WGS 84 geodetic", Crs::Wgs84.epsg(), WGS84_DEFINITION)];
+    if crs == Crs::Rd {
+        systems.push(("Amersfoort / RD New", srs_id, RD_DEFINITION));
+    }
+
+    for (srs_name, srs_id, definition) in systems {
+        transaction.execute(
+            "INSERT INTO gpkg_spatial_ref_sys VALUES (?1, ?2, 'EPSG', ?2, ?3, NULL)",
+            params![srs_name, srs_id, definition],
+        )?;
+    }
+
+    transaction.execute_batch(&format!(
+        "CREATE TABLE {t} (
+            fid INTEGER PRIMARY KEY AUTOINCREMENT,
+            point POINT NOT NULL,
+            postcode TEXT NOT NULL,
+            huisnummer TEXT NOT NULL
+        );
+        CREATE INDEX {t}_postcode ON {t} (postcode);
+        CREATE VIRTUAL TABLE rtree_{t}_point USING rtree(id, minx, maxx, miny, maxy);",
+        t = table
+    ))?;
+
+    let (mut min_x, mut min_y) = (f64::INFINITY, f64::INFINITY);
+    let (mut max_x, mut max_y) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+    let mut count = 0;
+
+    {
+        let mut insert = transaction.prepare(&format!(
+            "INSERT INTO {} (fid, point, postcode, huisnummer) VALUES (?1, ?2, ?3, ?4)",
+            table
+        ))?;
+        let mut insert_rtree = transaction.prepare(&format!(
+            "INSERT INTO rtree_{}_point VALUES (?1, ?2, ?2, ?3, ?3)",
+            table
+        ))?;
+
+        for (postcode, huisnummer, point) in addresses {
+            let (x, y) = crs.project(point);
+            let fid = count + 1;
+
+            let blob = point_blob(srs_id, x, y);
+            insert.execute(params![
+                fid,
+                &blob[..],
+                postcode.to_string(),
+                huisnummer.to_string()
+            ])?;
+            insert_rtree.execute(params![fid, x, y])?;
+
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+            count += 1;
+        }
+    }
+
+    // an empty table has no extent
+    let extent = (count > 0).then_some((min_x, min_y, max_x, max_y));
+
+    transaction.execute(
+        "INSERT INTO gpkg_contents (table_name, data_type, identifier, description, min_x, min_y, max_x, max_y, srs_id)
+         VALUES (?1, 'features', ?1, 'BAG addresses with their postcode', ?2, ?3, ?4, ?5, ?6)",
+        params![
+            table,
+            extent.map(|e| e.0),
+            extent.map(|e| e.1),
+            extent.map(|e| e.2),
+            extent.map(|e| e.3),
+            srs_id
+        ],
+    )?;
+    transaction.execute(
+        "INSERT INTO gpkg_geometry_columns VALUES (?1, 'point', 'POINT', ?2, 0, 0)",
+        params![table, srs_id],
+    )?;
+    transaction.execute(
+        "INSERT INTO gpkg_extensions VALUES (?1, 'point', 'gpkg_rtree_index', 'http://www.geopackage.org/spec120/#extension_rtree', 'write-only')",
+        params![table],
+    )?;
+
+    let triggers = RTREE_TRIGGERS
+        .replace("{t}", &table)
+        .replace("{c}", "point")
+        .replace("{i}", "fid");
+    transaction.execute_batch(&triggers)?;
+
+    transaction.commit()?;
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn points_and_rtree() {
+        let path = std::env::temp_dir().join("bagextract-test.gpkg");
+
+        let postcode = |input| Postcode::try_from(input).unwrap();
+        let addresses = vec![
+            (
+                postcode("1012BS"),
+                Huisnummer::new(12),
+                RdPoint::new(121_000.001, 487_000.0),
+            ),
+            (
+                postcode("1012BV"),
+                Huisnummer::new(3),
+                RdPoint::new(121_500.0, 487_500.0),
+            ),
+        ];
+
+        let count = write_gpkg(&path, Crs::Rd, addresses.into_iter()).unwrap();
+        assert_eq!(2, count);
+
+        let connection = Connection::open(&path).unwrap();
+
+        let application_id: i32 = connection
+            .query_row("PRAGMA application_id", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(APPLICATION_ID, application_id);
+
+        // the R-tree finds the first adres only
+        let query = "SELECT postcode, huisnummer, point FROM adressen_28992 WHERE fid IN (
+            SELECT id FROM rtree_adressen_28992_point
+            WHERE minx <= 121100 AND maxx >= 120900 AND miny <= 487100 AND maxy >= 486900
+        )";
+        let rows: Vec<(String, String, Vec<u8>)> = connection
+            .prepare(query)
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(1, rows.len());
+        assert_eq!(("1012BS", "12"), (rows[0].0.as_str(), rows[0].1.as_str()));
+        assert_eq!(
+            &point_blob(28992, 121_000.001, 487_000.0)[..],
+            &rows[0].2[..]
+        );
+
+        let max_x: f64 = connection
+            .query_row(
+                "SELECT max_x FROM gpkg_contents WHERE table_name = 'adressen_28992'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(121_500.0, max_x);
+
+        // the triggers exist, but need the ST_ functions of a GIS application
+        let triggers: i64 = connection
+            .query_row(
+                "SELECT count(*) FROM sqlite_master WHERE type = 'trigger'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(6, triggers);
+
+        let systems: i64 = connection
+            .query_row("SELECT count(*) FROM gpkg_spatial_ref_sys", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(4, systems);
+    }
+}
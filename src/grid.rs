@@ -0,0 +1,289 @@
+// A uniform grid over the RD New extent
+//
+// Every cell knows the addresses (points and their postcodes) that lie within it, so a query
+// only has to look at the few cells around its target instead of at every postcode.
+//
+// Like `Points`, the grid is stored as two arrays: one with the entries of all cells, ordered by
+// cell, and one that contains a `(start, length)` slice into the entries array for every cell.
+
+use crate::error::Error;
+use crate::index_file::{IndexFile, IndexFileWriter};
+use crate::memory_mapped_slice::{MemoryMappedSlice, Pod, Range};
+use crate::point::{BoundingBox, Coordinates, Point};
+use crate::points::PostcodePoints;
+use crate::postcode::Postcode;
+
+pub const GRID_CELLS_SECTION: &str = "grid-cells";
+pub const GRID_ENTRIES_SECTION: &str = "grid-entries";
+
+/// Width and height of a cell in meters
+pub const CELL_SIZE: f32 = 250.0;
+
+/// The extent of RD New (EPSG:28992). Points outside of it are put in the nearest border cell.
+pub const EXTENT: BoundingBox = BoundingBox {
+    min: Point::new(-7_000.0, 289_000.0),
+    max: Point::new(300_000.0, 629_000.0),
+};
+
+pub const COLUMNS: usize = ((EXTENT.max.x - EXTENT.min.x) / CELL_SIZE) as usize;
+pub const ROWS: usize = ((EXTENT.max.y - EXTENT.min.y) / CELL_SIZE) as usize;
+
+/// Something with a location, that can be stored in a grid
+pub trait Located {
+    type Coordinates: Coordinates;
+
+    fn point(&self) -> Self::Coordinates;
+}
+
+/// An address point and its postcode
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+pub struct GridEntry<P: Coordinates = Point> {
+    pub point: P,
+    postcode: u32,
+}
+
+// `Coordinates` are two 4-byte fields, followed by a u32, so no padding
+unsafe impl<P: Coordinates> Pod for GridEntry<P> {}
+const _: () = assert!(std::mem::size_of::<GridEntry>() == 12);
+
+impl<P: Coordinates> GridEntry<P> {
+    pub fn new(point: P, postcode: Postcode) -> Self {
+        Self {
+            point,
+            postcode: postcode.as_u32(),
+        }
+    }
+
+    pub fn postcode(&self) -> Postcode {
+        Postcode::from_u32(self.postcode)
+    }
+}
+
+impl<P: Coordinates> Located for GridEntry<P> {
+    type Coordinates = P;
+
+    fn point(&self) -> P {
+        self.point
+    }
+}
+
+fn column_of(x: f32) -> usize {
+    let column = ((x - EXTENT.min.x) / CELL_SIZE).floor();
+
+    column.clamp(0.0, (COLUMNS - 1) as f32) as usize
+}
+
+fn row_of(y: f32) -> usize {
+    let row = ((y - EXTENT.min.y) / CELL_SIZE).floor();
+
+    row.clamp(0.0, (ROWS - 1) as f32) as usize
+}
+
+/// Index of the cell that contains `point`
+pub fn cell_of(point: Point) -> usize {
+    row_of(point.y) * COLUMNS + column_of(point.x)
+}
+
+pub struct Grid<T: Pod = GridEntry> {
+    cells: MemoryMappedSlice<Range>,
+    entries: MemoryMappedSlice<T>,
+}
+
+impl<P: Coordinates> Grid<GridEntry<P>> {
+    /// Load the sections that `write_postcode_sections` wrote
+    pub fn from_index_file(index_file: &IndexFile) -> Result<Self, Error> {
+        Self::from_sections(index_file, GRID_CELLS_SECTION, GRID_ENTRIES_SECTION)
+    }
+
+    pub fn write_postcode_sections(
+        writer: &mut IndexFileWriter,
+        postcode_points: &PostcodePoints<P>,
+    ) -> std::io::Result<()> {
+        let entries = postcode_points.iter().flat_map(|(postcode, points)| {
+            points
+                .iter()
+                .map(move |point| GridEntry::new(*point, postcode))
+        });
+
+        Self::write_sections(writer, GRID_CELLS_SECTION, GRID_ENTRIES_SECTION, entries)
+    }
+}
+
+impl<T: Located + Pod> Grid<T> {
+    pub fn from_sections(
+        index_file: &IndexFile,
+        cells: &str,
+        entries: &str,
+    ) -> Result<Self, Error> {
+        let grid = Self {
+            cells: index_file.section(cells)?,
+            entries: index_file.section(entries)?,
+        };
+
+        if grid.cells.len() != COLUMNS * ROWS {
+            let message = format!(
+                "section {:?} has {} cells, expected {}",
+                cells,
+                grid.cells.len(),
+                COLUMNS * ROWS
+            );
+
+            return Err(Error::InvalidIndex {
+                path: index_file.path().display().to_string(),
+                message,
+            });
+        }
+
+        Ok(grid)
+    }
+
+    /// Put every entry in its cell.
+    ///
+    /// This is a counting sort: count the entries in every cell, turn the counts into start
+    /// offsets, then write every entry at the next free position of its cell.
+    pub fn write_sections<I>(
+        writer: &mut IndexFileWriter,
+        cells_section: &str,
+        entries_section: &str,
+        entries: I,
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = T> + Clone,
+    {
+        let mut cells = vec![Range::default(); COLUMNS * ROWS];
+
+        for entry in entries.clone() {
+            cells[cell_of(entry.point().to_point())].length += 1;
+        }
+
+        let mut start = 0;
+        for cell in cells.iter_mut() {
+            cell.start = start;
+            start += cell.length;
+        }
+
+        let mut next = cells.iter().map(|cell| cell.start).collect::<Vec<_>>();
+        // every slot is overwritten below, the first entry is just a placeholder
+        let mut sorted = match entries.clone().next() {
+            None => Vec::new(),
+            Some(first) => vec![first; start as usize],
+        };
+
+        for entry in entries {
+            let position = &mut next[cell_of(entry.point().to_point())];
+
+            sorted[*position as usize] = entry;
+            *position += 1;
+        }
+
+        writer.write_section(cells_section, &cells)?;
+        writer.write_section(entries_section, &sorted)?;
+
+        Ok(())
+    }
+
+    /// The entries in the given cell
+    pub fn cell(&self, cell: usize) -> &[T] {
+        self.cells.as_slice()[cell].of(self.entries.as_slice())
+    }
+
+    /// The entries of all cells that overlap with `bounding_box`
+    ///
+    /// This can include entries that are outside of the bounding box itself.
+    pub fn candidates(&self, bounding_box: &BoundingBox) -> impl Iterator<Item = &T> {
+        let columns = column_of(bounding_box.min.x)..=column_of(bounding_box.max.x);
+        let rows = row_of(bounding_box.min.y)..=row_of(bounding_box.max.y);
+
+        rows.flat_map(move |row| {
+            columns
+                .clone()
+                .flat_map(move |column| self.cell(row * COLUMNS + column))
+        })
+    }
+
+    /// The entries within `radius` meters of `target`
+    pub fn within_radius(&self, target: T::Coordinates, radius: f32) -> impl Iterator<Item = &T> {
+        // the box is a meter larger, so rounding to f32 cannot make it miss a cell
+        let bounding_box = BoundingBox::around(target.to_point(), radius + 1.0);
+        let squared_radius = T::Coordinates::squared(radius);
+
+        self.candidates(&bounding_box)
+            .filter(move |entry| entry.point().squared_distance_to(&target) <= squared_radius)
+    }
+
+    /// The entry that is closest to `target`, and its distance to `target`
+    pub fn nearest(&self, target: T::Coordinates) -> Option<(&T, f32)> {
+        for radius in search_radii(target.to_point()) {
+            let nearest = self
+                .within_radius(target, radius)
+                .map(|entry| (entry, entry.point().squared_distance_to(&target)))
+                .min_by(|a, b| T::Coordinates::cmp_squared(&a.1, &b.1));
+
+            if let Some((entry, squared)) = nearest {
+                return Some((entry, T::Coordinates::distance(squared)));
+            }
+        }
+
+        None
+    }
+}
+
+/// Ever growing search radii around `target`
+///
+/// Every radius is double the previous one, until the circle covers the whole extent. The last
+/// radius is infinite, so every entry is found eventually (also those outside of the extent).
+pub fn search_radii(target: Point) -> impl Iterator<Item = f32> {
+    let corners = [
+        EXTENT.min,
+        EXTENT.max,
+        Point::new(EXTENT.min.x, EXTENT.max.y),
+        Point::new(EXTENT.max.x, EXTENT.min.y),
+    ];
+    let covers_extent = corners
+        .iter()
+        .map(|corner| corner.distance_to(&target))
+        .fold(0.0, f32::max);
+
+    let mut radius = CELL_SIZE;
+
+    std::iter::from_fn(move || {
+        let current = radius;
+
+        if current.is_infinite() {
+            None
+        } else {
+            radius = if current >= covers_extent {
+                f32::INFINITY
+            } else {
+                current * 2.0
+            };
+
+            Some(current)
+        }
+    })
+    .chain(std::iter::once(f32::INFINITY))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cells() {
+        assert_eq!(0, cell_of(EXTENT.min));
+        assert_eq!(0, cell_of(Point::new(-10_000.0, 0.0)));
+        assert_eq!(COLUMNS * ROWS - 1, cell_of(EXTENT.max));
+        assert_eq!(COLUMNS + 1, cell_of(Point::new(-6_700.0, 289_300.0)));
+    }
+
+    #[test]
+    fn radii() {
+        let radii: Vec<f32> = search_radii(Point::new(150_000.0, 450_000.0)).collect();
+
+        assert_eq!(Some(&CELL_SIZE), radii.first());
+        assert_eq!(Some(&f32::INFINITY), radii.last());
+        assert_eq!(1, radii.iter().filter(|r| r.is_infinite()).count());
+        assert!(radii[radii.len() - 2] >= EXTENT.min.distance_to(&EXTENT.max) / 2.0);
+    }
+}
@@ -0,0 +1,477 @@
+// A single file that contains all generated arrays
+//
+// The file starts with a fixed-size header, followed by the sections. Every section is the raw
+// bytes of one array (e.g. the points, or the cells of a grid) and starts at a 64-byte aligned
+// offset, so it can be memory mapped and cast to a slice of its element type.
+//
+// Header layout (all integers in the byte order of the machine that wrote the file):
+//
+// | offset | size    | field                                                    |
+// |--------|---------|----------------------------------------------------------|
+// | 0      | 8       | magic, `BAGINDEX`                                        |
+// | 8      | 4       | format version                                           |
+// | 12     | 4       | endianness marker, `0x01020304`                          |
+// | 16     | 4       | date of the source extract as `yyyymmdd`, 0 if unknown   |
+// | 20     | 4       | number of sections                                       |
+// | 24     | 4       | crc32 of the header, computed with this field zeroed     |
+// | 28     | 4       | coordinates: 0 for f32 meters, 1 for i32 millimeters     |
+// | 32     | 48 * 16 | the section table                                        |
+//
+// A section table entry is the name of the section (zero padded), the size of an element, the
+// crc32 of the section's bytes, and the offset and length (in bytes) of the section.
+//
+// The file is written to `<path>.tmp`, and only renamed to `<path>` once it is complete, so a
+// failed write never replaces a good file.
+//
+// Opening a file checks the header and that all sections are within the file. Checking the
+// section checksums means reading the whole file, so that is a separate step (`verify`).
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::Error;
+use crate::memory_mapped_slice::{as_bytes, MemoryMappedSlice, Pod};
+
+pub const INDEX_FILE: &str = "index-28992.bin";
+
+const MAGIC: [u8; 8] = *b"BAGINDEX";
+const VERSION: u32 = 2;
+const ENDIANNESS: u32 = 0x0102_0304;
+
+const MAX_SECTIONS: usize = 16;
+const NAME_SIZE: usize = 24;
+const ENTRY_SIZE: usize = NAME_SIZE + 4 + 4 + 8 + 8;
+const HEADER_SIZE: usize = 32 + MAX_SECTIONS * ENTRY_SIZE;
+
+const CHECKSUM_RANGE: std::ops::Range<usize> = 24..28;
+
+/// Sections start at a multiple of this, which is enough for the alignment of every element type
+const ALIGNMENT: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub element_size: u32,
+    pub checksum: u32,
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl Section {
+    /// `None` when a corrupted header gives a section beyond `u64::MAX`
+    fn end(&self) -> Option<u64> {
+        self.offset.checked_add(self.length)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Header {
+    extract_date: u32,
+    coordinates: u32,
+    sections: Vec<Section>,
+}
+
+impl Header {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; HEADER_SIZE];
+
+        bytes[0..8].copy_from_slice(&MAGIC);
+        bytes[8..12].copy_from_slice(&VERSION.to_ne_bytes());
+        bytes[12..16].copy_from_slice(&ENDIANNESS.to_ne_bytes());
+        bytes[16..20].copy_from_slice(&self.extract_date.to_ne_bytes());
+        bytes[20..24].copy_from_slice(&(self.sections.len() as u32).to_ne_bytes());
+        bytes[28..32].copy_from_slice(&self.coordinates.to_ne_bytes());
+
+        for (section, entry) in self.sections.iter().zip(bytes[32..].chunks_mut(ENTRY_SIZE)) {
+            entry[..section.name.len()].copy_from_slice(section.name.as_bytes());
+            entry[24..28].copy_from_slice(&section.element_size.to_ne_bytes());
+            entry[28..32].copy_from_slice(&section.checksum.to_ne_bytes());
+            entry[32..40].copy_from_slice(&section.offset.to_ne_bytes());
+            entry[40..48].copy_from_slice(&section.length.to_ne_bytes());
+        }
+
+        let checksum = crc32fast::hash(&bytes);
+        bytes[CHECKSUM_RANGE].copy_from_slice(&checksum.to_ne_bytes());
+
+        bytes
+    }
+
+    fn from_bytes(mut bytes: Vec<u8>) -> Result<Self, String> {
+        let u32_at =
+            |bytes: &[u8], at: usize| u32::from_ne_bytes(bytes[at..][..4].try_into().unwrap());
+        let u64_at =
+            |bytes: &[u8], at: usize| u64::from_ne_bytes(bytes[at..][..8].try_into().unwrap());
+
+        if bytes.len() < HEADER_SIZE || bytes[0..8] != MAGIC {
+            return Err("not an index file".to_string());
+        }
+
+        if u32_at(&bytes, 12) != ENDIANNESS {
+            return Err("written on a machine with a different byte order".to_string());
+        }
+
+        let version = u32_at(&bytes, 8);
+        if version != VERSION {
+            let message = format!("format version {}, expected {}", version, VERSION);
+            return Err(message);
+        }
+
+        let checksum = u32_at(&bytes, CHECKSUM_RANGE.start);
+        bytes[CHECKSUM_RANGE].fill(0);
+        if crc32fast::hash(&bytes) != checksum {
+            return Err("header checksum mismatch".to_string());
+        }
+
+        let section_count = u32_at(&bytes, 20) as usize;
+        if section_count > MAX_SECTIONS {
+            return Err(format!(
+                "{} sections, at most {}",
+                section_count, MAX_SECTIONS
+            ));
+        }
+
+        let sections = bytes[32..]
+            .chunks(ENTRY_SIZE)
+            .take(section_count)
+            .map(|entry| {
+                let name = &entry[..NAME_SIZE];
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(NAME_SIZE)];
+
+                Section {
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    element_size: u32_at(entry, 24),
+                    checksum: u32_at(entry, 28),
+                    offset: u64_at(entry, 32),
+                    length: u64_at(entry, 40),
+                }
+            })
+            .collect();
+
+        Ok(Header {
+            extract_date: u32_at(&bytes, 16),
+            coordinates: u32_at(&bytes, 28),
+            sections,
+        })
+    }
+}
+
+/// Writes an index file, one section at a time
+///
+/// The header is written last (by `finish`), so an interrupted write leaves a file that is
+/// rejected when it is opened. Until then the file is `<path>.tmp`, and `finish` renames it.
+pub struct IndexFileWriter {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: std::io::BufWriter<File>,
+    position: u64,
+    header: Header,
+}
+
+impl IndexFileWriter {
+    /// `extract_date` is the date of the source extract as `yyyymmdd`, or 0 when it is unknown.
+    /// `coordinates` is the `Coordinates::KIND` of the stored points.
+    pub fn create<P: AsRef<Path>>(
+        path: P,
+        extract_date: u32,
+        coordinates: u32,
+    ) -> std::io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut file = std::io::BufWriter::new(File::create(&tmp)?);
+
+        // placeholder, the actual header is only known at the end
+        file.write_all(&[0; HEADER_SIZE])?;
+
+        let writer = Self {
+            path,
+            tmp,
+            file,
+            position: HEADER_SIZE as u64,
+            header: Header {
+                extract_date,
+                coordinates,
+                sections: Vec::new(),
+            },
+        };
+
+        Ok(writer)
+    }
+
+    pub fn write_section<T: Pod>(&mut self, name: &str, slice: &[T]) -> std::io::Result<()> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+
+        if name.is_empty() || name.len() > NAME_SIZE || name.contains('\0') {
+            return Err(invalid(format!("invalid section name {:?}", name)));
+        }
+
+        if self
+            .header
+            .sections
+            .iter()
+            .any(|section| section.name == name)
+        {
+            return Err(invalid(format!("duplicate section {:?}", name)));
+        }
+
+        if self.header.sections.len() == MAX_SECTIONS {
+            return Err(invalid(format!("more than {} sections", MAX_SECTIONS)));
+        }
+
+        let offset = self.position.div_ceil(ALIGNMENT) * ALIGNMENT;
+        let padding = (offset - self.position) as usize;
+        self.file.write_all(&[0; ALIGNMENT as usize][..padding])?;
+
+        let bytes = as_bytes(slice);
+        self.file.write_all(bytes)?;
+
+        self.position = offset + bytes.len() as u64;
+        self.header.sections.push(Section {
+            name: name.to_string(),
+            element_size: std::mem::size_of::<T>() as u32,
+            checksum: crc32fast::hash(bytes),
+            offset,
+            length: bytes.len() as u64,
+        });
+
+        Ok(())
+    }
+
+    pub fn finish(self) -> std::io::Result<()> {
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&self.header.to_bytes())?;
+        file.sync_all()?;
+
+        std::fs::rename(&self.tmp, &self.path)
+    }
+}
+
+/// An index file that has been checked to be complete, and written by a compatible version
+pub struct IndexFile {
+    path: PathBuf,
+    file: File,
+    header: Header,
+}
+
+impl IndexFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        use std::io::Read;
+
+        let path = path.as_ref().to_path_buf();
+        let invalid = |message: String| Error::InvalidIndex {
+            path: path.display().to_string(),
+            message,
+        };
+
+        let mut file = File::open(&path)?;
+        let file_length = file.metadata()?.len();
+
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+        (&mut file)
+            .take(HEADER_SIZE as u64)
+            .read_to_end(&mut bytes)?;
+
+        let header = Header::from_bytes(bytes).map_err(invalid)?;
+
+        let ends: Option<Vec<u64>> = header.sections.iter().map(Section::end).collect();
+        let ends = ends.ok_or_else(|| invalid("a section ends beyond u64::MAX".to_string()))?;
+
+        let end = ends.into_iter().max();
+        let expected_length = end.unwrap_or(HEADER_SIZE as u64).max(HEADER_SIZE as u64);
+        if file_length != expected_length {
+            let message = format!(
+                "file is {} bytes, expected {} (truncated or corrupted)",
+                file_length, expected_length
+            );
+            return Err(invalid(message));
+        }
+
+        for section in header.sections.iter() {
+            if section.offset < HEADER_SIZE as u64 || !section.offset.is_multiple_of(ALIGNMENT) {
+                let message = format!("section {:?} is not aligned", section.name);
+                return Err(invalid(message));
+            }
+        }
+
+        Ok(Self { path, file, header })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The date of the source extract as `yyyymmdd`, if it is known
+    pub fn extract_date(&self) -> Option<u32> {
+        match self.header.extract_date {
+            0 => None,
+            date => Some(date),
+        }
+    }
+
+    /// The `Coordinates::KIND` of the stored points
+    pub fn coordinates(&self) -> u32 {
+        self.header.coordinates
+    }
+
+    pub fn sections(&self) -> &[Section] {
+        &self.header.sections
+    }
+
+    pub fn has_section(&self, name: &str) -> bool {
+        self.header
+            .sections
+            .iter()
+            .any(|section| section.name == name)
+    }
+
+    fn invalid(&self, message: String) -> Error {
+        Error::InvalidIndex {
+            path: self.path.display().to_string(),
+            message,
+        }
+    }
+
+    fn find(&self, name: &str) -> Result<&Section, Error> {
+        self.header
+            .sections
+            .iter()
+            .find(|section| section.name == name)
+            .ok_or_else(|| self.invalid(format!("missing section {:?}", name)))
+    }
+
+    /// Memory map the section with the given name
+    pub fn section<T: Pod>(&self, name: &str) -> Result<MemoryMappedSlice<T>, Error> {
+        let section = self.find(name)?;
+        let element_size = std::mem::size_of::<T>();
+
+        if section.element_size as usize != element_size {
+            let message = format!(
+                "section {:?} has elements of {} bytes, expected {}",
+                name, section.element_size, element_size
+            );
+            return Err(self.invalid(message));
+        }
+
+        if !section.length.is_multiple_of(element_size as u64) {
+            let message = format!("section {:?} has a partial element", name);
+            return Err(self.invalid(message));
+        }
+
+        let slice = MemoryMappedSlice::from_section(&self.file, section.offset, section.length)?;
+
+        Ok(slice)
+    }
+
+    /// Check the checksum of every section. This reads the whole file.
+    pub fn verify(&self) -> Result<(), Error> {
+        for section in self.header.sections.iter() {
+            let slice: MemoryMappedSlice<u8> =
+                MemoryMappedSlice::from_section(&self.file, section.offset, section.length)?;
+
+            if crc32fast::hash(slice.as_slice()) != section.checksum {
+                let message = format!("checksum mismatch in section {:?}", section.name);
+                return Err(self.invalid(message));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::memory_mapped_slice::Range;
+
+    fn write_test_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+
+        let mut writer = IndexFileWriter::create(&path, 20211008, 0).unwrap();
+        writer.write_section("bytes", &[1u8, 2, 3]).unwrap();
+        writer
+            .write_section("pairs", &[Range::new(1, 2), Range::new(3, 4)])
+            .unwrap();
+        writer.write_section::<u64>("empty", &[]).unwrap();
+        writer.finish().unwrap();
+
+        path
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = write_test_file("bagextract-index-file-round-trip.bin");
+        let index = IndexFile::open(&path).unwrap();
+
+        assert_eq!(Some(20211008), index.extract_date());
+        index.verify().unwrap();
+
+        let pairs = index.section::<Range>("pairs").unwrap();
+        assert_eq!(&[Range::new(1, 2), Range::new(3, 4)], pairs.as_slice());
+        assert_eq!(&[1, 2, 3], index.section::<u8>("bytes").unwrap().as_slice());
+        assert!(index.section::<u64>("empty").unwrap().is_empty());
+
+        assert!(index.section::<u32>("pairs").is_err());
+        assert!(index.section::<u8>("points").is_err());
+    }
+
+    #[test]
+    fn failed_write_keeps_file() {
+        let path = write_test_file("bagextract-index-file-failed-write.bin");
+        let bytes = std::fs::read(&path).unwrap();
+
+        let mut writer = IndexFileWriter::create(&path, 20211009, 0).unwrap();
+        writer.write_section("bytes", &[4u8, 5, 6]).unwrap();
+        assert!(writer.write_section("bytes", &[7u8]).is_err());
+        drop(writer);
+
+        assert_eq!(bytes, std::fs::read(&path).unwrap());
+        assert_eq!(
+            Some(20211008),
+            IndexFile::open(&path).unwrap().extract_date()
+        );
+    }
+
+    #[test]
+    fn rejected() {
+        let path = write_test_file("bagextract-index-file-rejected.bin");
+        let bytes = std::fs::read(&path).unwrap();
+
+        // truncated
+        std::fs::write(&path, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(IndexFile::open(&path).is_err());
+
+        // another format version
+        let mut changed = bytes.clone();
+        changed[8] += 1;
+        std::fs::write(&path, &changed).unwrap();
+        assert!(IndexFile::open(&path).is_err());
+
+        // the header is corrupted
+        let mut changed = bytes.clone();
+        changed[40] ^= 1;
+        std::fs::write(&path, &changed).unwrap();
+        assert!(IndexFile::open(&path).is_err());
+
+        // a section that overflows, with a valid header checksum
+        let mut header = Header::from_bytes(bytes[..HEADER_SIZE].to_vec()).unwrap();
+        header.sections[0].length = u64::MAX;
+        let mut changed = bytes.clone();
+        changed[..HEADER_SIZE].copy_from_slice(&header.to_bytes());
+        std::fs::write(&path, &changed).unwrap();
+        assert!(IndexFile::open(&path).is_err());
+
+        // a section is corrupted
+        let first_section = (HEADER_SIZE as u64).div_ceil(ALIGNMENT) * ALIGNMENT;
+        let mut changed = bytes;
+        changed[first_section as usize + 1] ^= 1;
+        std::fs::write(&path, &changed).unwrap();
+        let index = IndexFile::open(&path).unwrap();
+        assert!(index.verify().is_err());
+    }
+}
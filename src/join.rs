@@ -0,0 +1,259 @@
+// Join every Verblijfsobject with its hoofdadres: the Nummeraanduiding with the same identificatie
+//
+// There are two ways to do that. The hash join builds a `HashMap` of all ~10M
+// Nummeraanduidingen, and probes it once per Verblijfsobject. The sort-merge join sorts both
+// sides by identificatie (in parallel), and then walks through them in lockstep. That needs no
+// map, and also finds the Nummeraanduidingen that no Verblijfsobject refers to.
+//
+// Either way the addresses come out sorted by the identificatie of the Nummeraanduiding, and then
+// of the Verblijfsobject. So the index file does not depend on the order of the records in the
+// extract, and an index that is updated with mutations (see `crate::mutations`) is the same as
+// one that is generated from scratch.
+
+use std::collections::HashMap;
+
+use crate::adres::{Adres, Huisnummer};
+use crate::parse_num::Postcodes;
+use crate::parse_vbo::Verblijfsobjecten;
+use crate::postcode::Postcode;
+use crate::report::{Reason, Report};
+
+/// How to join the Verblijfsobjecten with the Nummeraanduidingen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    HashMap,
+    SortMerge,
+}
+
+/// Join with the given method, and add the records that did not match to `report`
+///
+/// The addresses are sorted by identificatie, then by the identificatie of the Verblijfsobject.
+pub fn join(
+    method: Method,
+    verblijfsobjecten: Verblijfsobjecten,
+    nummeraanduidingen: Postcodes,
+    report: &mut Report,
+) -> Vec<Adres> {
+    use rayon::prelude::*;
+
+    match method {
+        Method::HashMap => {
+            let mut addresses = hash_join(verblijfsobjecten, nummeraanduidingen, report);
+            addresses
+                .par_sort_unstable_by_key(|adres| (adres.identificatie, adres.verblijfsobject));
+            addresses
+        }
+        Method::SortMerge => sort_merge_join(verblijfsobjecten, nummeraanduidingen, report),
+    }
+}
+
+/// Join using a map from identificatie to Nummeraanduiding
+///
+/// The addresses are in the order of the Verblijfsobjecten. Only the Verblijfsobjecten that do
+/// not match are reported.
+pub fn hash_join(
+    verblijfsobjecten: Verblijfsobjecten,
+    nummeraanduidingen: Postcodes,
+    report: &mut Report,
+) -> Vec<Adres> {
+    let it = nummeraanduidingen.identificatie.into_iter().zip(
+        nummeraanduidingen
+            .postcodes
+            .into_iter()
+            .zip(nummeraanduidingen.huisnummers),
+    );
+    let map: HashMap<u64, (Postcode, Huisnummer)> = it.collect();
+
+    let it = verblijfsobjecten
+        .postcode_id
+        .into_iter()
+        .zip(verblijfsobjecten.identificatie)
+        .zip(verblijfsobjecten.points);
+
+    let mut addresses = Vec::with_capacity(map.len());
+
+    for ((id, verblijfsobject), point) in it {
+        match map.get(&id) {
+            None => {
+                let reason = Reason::UnknownNummeraanduiding;
+                report.skip(reason, || id.to_string());
+            }
+            Some((postcode, huisnummer)) => {
+                addresses.push(Adres {
+                    identificatie: id,
+                    verblijfsobject,
+                    postcode: *postcode,
+                    huisnummer: *huisnummer,
+                    point,
+                });
+            }
+        }
+    }
+
+    addresses
+}
+
+/// Join by sorting both sides on identificatie, and merging them
+///
+/// The addresses are sorted by identificatie, then by the identificatie of the Verblijfsobject.
+/// Verblijfsobjecten without a matching
+/// Nummeraanduiding are reported as skipped. The Nummeraanduidingen that are not the hoofdadres
+/// of any Verblijfsobject are reported too, but separately: they are not skipped records.
+pub fn sort_merge_join(
+    verblijfsobjecten: Verblijfsobjecten,
+    nummeraanduidingen: Postcodes,
+    report: &mut Report,
+) -> Vec<Adres> {
+    use rayon::prelude::*;
+
+    let mut objects: Vec<_> = verblijfsobjecten
+        .postcode_id
+        .into_iter()
+        .zip(verblijfsobjecten.identificatie)
+        .zip(verblijfsobjecten.points)
+        .map(|((id, verblijfsobject), point)| (id, verblijfsobject, point))
+        .collect();
+
+    let mut numbers: Vec<_> = nummeraanduidingen
+        .identificatie
+        .into_iter()
+        .zip(nummeraanduidingen.postcodes)
+        .zip(nummeraanduidingen.huisnummers)
+        .map(|((id, postcode), huisnummer)| (id, postcode, huisnummer))
+        .collect();
+
+    // stable, so that the result does not depend on the scheduling of the threads
+    rayon::join(
+        || objects.par_sort_by_key(|(id, verblijfsobject, _)| (*id, *verblijfsobject)),
+        || numbers.par_sort_by_key(|(id, _, _)| *id),
+    );
+
+    let mut addresses = Vec::with_capacity(objects.len());
+
+    let unknown = |report: &mut Report, id: u64| {
+        report.skip(Reason::UnknownNummeraanduiding, || id.to_string());
+    };
+    report.count_unreferenced();
+    let unreferenced = |report: &mut Report, id: u64| {
+        report.unreferenced(|| id.to_string());
+    };
+
+    let mut objects = objects.into_iter().peekable();
+    let mut numbers = numbers.into_iter().peekable();
+
+    // whether the current Nummeraanduiding has been joined with a Verblijfsobject
+    let mut referenced = false;
+
+    while let (Some((id, verblijfsobject, point)), Some((number_id, postcode, huisnummer))) =
+        (objects.peek(), numbers.peek())
+    {
+        match id.cmp(number_id) {
+            std::cmp::Ordering::Less => {
+                unknown(report, *id);
+                objects.next();
+            }
+            std::cmp::Ordering::Greater => {
+                if !referenced {
+                    unreferenced(report, *number_id);
+                }
+                referenced = false;
+                numbers.next();
+            }
+            std::cmp::Ordering::Equal => {
+                // several Verblijfsobjecten can have the same hoofdadres
+                addresses.push(Adres {
+                    identificatie: *id,
+                    verblijfsobject: *verblijfsobject,
+                    postcode: *postcode,
+                    huisnummer: *huisnummer,
+                    point: *point,
+                });
+                referenced = true;
+                objects.next();
+            }
+        }
+    }
+
+    for (id, _, _) in objects {
+        unknown(report, id);
+    }
+
+    for (id, _, _) in numbers {
+        if !referenced {
+            unreferenced(report, id);
+        }
+        referenced = false;
+    }
+
+    addresses
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::point::MillimeterPoint;
+    use crate::status::{Status, Voorkomen};
+
+    #[test]
+    fn methods_agree() {
+        let postcode = |input| Postcode::try_from(input).unwrap();
+
+        let verblijfsobjecten = || Verblijfsobjecten {
+            identificatie: (100..105).collect(),
+            points: (0..5).map(|i| MillimeterPoint::new(i, i)).collect(),
+            postcode_id: vec![30, 10, 40, 10, 20],
+            status: vec![Status::InGebruik; 5],
+            voorkomens: vec![Voorkomen::default(); 5],
+            report: Report::default(),
+        };
+
+        let nummeraanduidingen = || Postcodes {
+            identificatie: vec![20, 50, 10, 30],
+            postcodes: vec![
+                postcode("1012BS"),
+                postcode("1012BT"),
+                postcode("1012BU"),
+                postcode("1012BV"),
+            ],
+            huisnummers: (1..=4).map(Huisnummer::new).collect(),
+            status: vec![Status::NaamgevingUitgegeven; 4],
+            voorkomens: vec![Voorkomen::default(); 4],
+            report: Report::default(),
+        };
+
+        let mut hash_report = Report::default();
+        let mut hashed = hash_join(verblijfsobjecten(), nummeraanduidingen(), &mut hash_report);
+
+        let mut merge_report = Report::default();
+        let merged = sort_merge_join(verblijfsobjecten(), nummeraanduidingen(), &mut merge_report);
+
+        // the sort-merge join is sorted by identificatie, then by Verblijfsobject
+        let ids: Vec<u64> = merged.iter().map(|adres| adres.identificatie).collect();
+        assert_eq!(vec![10, 10, 20, 30], ids);
+        assert_eq!(MillimeterPoint::new(1, 1), merged[0].point);
+        assert_eq!(MillimeterPoint::new(3, 3), merged[1].point);
+        assert_eq!(103, merged[1].verblijfsobject);
+
+        hashed.sort_by_key(|adres| adres.identificatie);
+        assert_eq!(hashed, merged);
+
+        // `join` gives the same order with both methods
+        let mut report = Report::default();
+        let method = Method::HashMap;
+        let joined = join(
+            method,
+            verblijfsobjecten(),
+            nummeraanduidingen(),
+            &mut report,
+        );
+        assert_eq!(joined, merged);
+
+        let unknown = Reason::UnknownNummeraanduiding;
+        assert_eq!(hash_report.get(unknown), merge_report.get(unknown));
+        assert_eq!(vec!["40"], merge_report.get(unknown).unwrap().samples);
+
+        let unreferenced = merge_report.get_unreferenced().unwrap();
+        assert_eq!(vec!["50"], unreferenced.samples);
+        assert_eq!(None, hash_report.get_unreferenced());
+    }
+}
@@ -1,5 +1,24 @@
+pub mod adres;
+pub mod crs;
+pub mod database;
+pub mod error;
+pub mod export;
+pub mod geocode;
+pub mod geoparquet;
+pub mod gpkg;
+pub mod grid;
+pub mod index_file;
+pub mod join;
 pub mod memory_mapped_slice;
+pub mod mutations;
 pub mod parse_num;
 pub mod parse_vbo;
 pub mod point;
+pub mod points;
 pub mod postcode;
+pub mod query;
+pub mod report;
+pub mod source;
+pub mod status;
+
+pub use error::Error;
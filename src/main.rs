@@ -1,279 +1,1092 @@
-use std::collections::hash_map::HashMap;
 use std::path::{Path, PathBuf};
 
 extern crate bagextract;
 
 use bagextract::*;
 
-use memory_mapped_slice::MemoryMappedSlice;
-use point::Point;
+use adres::{Adres, Toevoeging};
+use crs::{Crs, RdPoint, Wgs84Point};
+use geocode::Addresses;
+use index_file::{IndexFile, INDEX_FILE};
+use point::{Coordinates, MillimeterPoint, Point};
+use points::{Points, PostcodeLayout, PostcodePoints};
 use postcode::Postcode;
+use report::{Mode, Report};
+use source::ZipSource;
 
-fn main() -> std::io::Result<()> {
+fn main() -> Result<(), Error> {
     use clap::{App, Arg, SubCommand};
 
-    let app = App::new("bag-extract").subcommand(
-        SubCommand::with_name("generate")
-            .about("extract postcode <-> location data from inspireadressen")
-            .arg(
-                Arg::with_name("SOURCE_DIR")
-                    .short("s")
-                    .long("source")
-                    .help("Path of the input directory")
-                    .default_value("/home/folkertdev/Downloads/inspire"),
-            )
-            .arg(
-                Arg::with_name("HOST")
-                    .long("host")
-                    .help("database host")
-                    .default_value("localhost"),
-            )
-            .arg(
-                Arg::with_name("USER")
-                    .long("user")
-                    .help("database user")
-                    .default_value("tgbag"),
-            )
-            .arg(
-                Arg::with_name("PASSWORD")
-                    .long("password")
-                    .help("database password")
-                    .default_value("tgbag"),
-            )
-            .arg(
-                Arg::with_name("DBNAME")
-                    .long("dbname")
-                    .help("database dbname")
-                    .default_value("bagextract"),
-            ),
-    );
+    let mut status_names: Vec<&str> = status::Status::ALL.iter().map(|s| s.name()).collect();
+    status_names.push("all");
+
+    let app = App::new("bag-extract")
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("extract postcode <-> location data from inspireadressen")
+                .arg(
+                    Arg::with_name("SOURCE")
+                        .short("s")
+                        .long("source")
+                        .help("lvbag-extract-nl.zip, or a directory with vbo.zip and num.zip")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("LENIENT")
+                        .long("lenient")
+                        .help("skip malformed records, and write a report of skipped records"),
+                )
+                .arg(
+                    Arg::with_name("MILLIMETERS")
+                        .long("millimeters")
+                        .help("store coordinates exactly, as integer millimeters"),
+                )
+                .arg(
+                    Arg::with_name("SPARSE")
+                        .long("sparse")
+                        .help("only store the postcodes that occur, for a smaller index file"),
+                )
+                .arg(
+                    Arg::with_name("JOIN")
+                        .long("join")
+                        .help("how to join the Verblijfsobjecten with their Nummeraanduiding")
+                        .possible_values(&["hashmap", "sort-merge"])
+                        .default_value("hashmap"),
+                )
+                .arg(
+                    Arg::with_name("STATUS")
+                        .long("status")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .possible_values(&status_names)
+                        .help("keep the objects with these statuses, or all (default: in-gebruik, in-gebruik-niet-ingemeten, verbouwing and naamgeving-uitgegeven)"),
+                )
+                .arg(
+                    Arg::with_name("STATE")
+                        .long("state")
+                        .help("also store the parsed objects, so that apply-mutations can update the index"),
+                )
+                .arg(
+                    Arg::with_name("GPKG")
+                        .long("gpkg")
+                        .takes_value(true)
+                        .help("write the addresses to this GeoPackage, instead of to the database"),
+                )
+                .arg(
+                    Arg::with_name("UPDATE")
+                        .long("update")
+                        .possible_values(&["table", "index"])
+                        .conflicts_with("GPKG")
+                        .help("only apply the changes to the database, compared with its current contents or with the previous index file"),
+                )
+                .arg(
+                    Arg::with_name("GEOPARQUET")
+                        .long("geoparquet")
+                        .takes_value(true)
+                        .help("also write the addresses to this GeoParquet file, sorted by postcode"),
+                )
+                .arg(
+                    Arg::with_name("DATABASE_URL")
+                        .long("database-url")
+                        .takes_value(true)
+                        .conflicts_with_all(&["HOST", "USER", "PASSWORD", "DBNAME"])
+                        .help("libpq connection string or postgresql:// URL; the PG* environment variables fill in the rest"),
+                )
+                .arg(
+                    Arg::with_name("HOST")
+                        .long("host")
+                        .takes_value(true)
+                        .help("database host (default: PGHOST, or localhost)"),
+                )
+                .arg(
+                    Arg::with_name("USER")
+                        .long("user")
+                        .takes_value(true)
+                        .help("database user (default: PGUSER)"),
+                )
+                .arg(
+                    Arg::with_name("PASSWORD")
+                        .long("password")
+                        .takes_value(true)
+                        .help("database password (default: PGPASSWORD)"),
+                )
+                .arg(
+                    Arg::with_name("DBNAME")
+                        .long("dbname")
+                        .takes_value(true)
+                        .help("database dbname (default: PGDATABASE)"),
+                )
+                .arg(
+                    Arg::with_name("TLS")
+                        .long("tls")
+                        .help("require TLS, like sslmode=require"),
+                )
+                .arg(
+                    Arg::with_name("SSL_ROOT_CERT")
+                        .long("ssl-root-cert")
+                        .takes_value(true)
+                        .help("also trust this (PEM) certificate for TLS (default: PGSSLROOTCERT)"),
+                )
+                .arg(
+                    Arg::with_name("SCHEMA")
+                        .long("schema")
+                        .help("schema of the table to load, which must exist")
+                        .default_value("public"),
+                )
+                .arg(
+                    Arg::with_name("TABLE")
+                        .long("table")
+                        .takes_value(true)
+                        .help("table to load (default: adressen_<srid>)"),
+                )
+                .arg(
+                    Arg::with_name("SRID")
+                        .long("srid")
+                        .help("coordinate system of the loaded points: RD New or WGS84")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("find the postcodes within a radius of a point")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("X")
+                        .help("x coordinate (longitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("Y")
+                        .help("y coordinate (latitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("CRS")
+                        .long("crs")
+                        .help("coordinate system of the point: 28992 (RD New) or 4326 (WGS84)")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                )
+                .arg(
+                    Arg::with_name("RADIUS")
+                        .short("r")
+                        .long("radius")
+                        .help("search radius in meters")
+                        .default_value("50"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("nearest")
+                .about("find the postcodes closest to a point")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("X")
+                        .help("x coordinate (longitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("Y")
+                        .help("y coordinate (latitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("CRS")
+                        .long("crs")
+                        .help("coordinate system of the point: 28992 (RD New) or 4326 (WGS84)")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                )
+                .arg(
+                    Arg::with_name("COUNT")
+                        .short("k")
+                        .long("count")
+                        .help("number of postcodes to return")
+                        .default_value("1"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("geocode")
+                .about("find the location of an adres")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("POSTCODE")
+                        .help("e.g. 1012BS")
+                        .required(true),
+                )
+                .arg(Arg::with_name("HUISNUMMER").help("e.g. 12").required(true))
+                .arg(
+                    Arg::with_name("HUISLETTER")
+                        .long("huisletter")
+                        .takes_value(true)
+                        .help("e.g. A"),
+                )
+                .arg(
+                    Arg::with_name("TOEVOEGING")
+                        .long("toevoeging")
+                        .takes_value(true)
+                        .help("e.g. bis"),
+                )
+                .arg(
+                    Arg::with_name("CRS")
+                        .long("crs")
+                        .help("coordinate system of the output: 28992 (RD New) or 4326 (WGS84)")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("reverse")
+                .about("find the adres closest to a point")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("X")
+                        .help("x coordinate (longitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("Y")
+                        .help("y coordinate (latitude for 4326)")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("CRS")
+                        .long("crs")
+                        .help("coordinate system of the point: 28992 (RD New) or 4326 (WGS84)")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("write the points of the generated index file as GeoJSON or CSV")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("FORMAT")
+                        .short("f")
+                        .long("format")
+                        .help("a MultiPoint per postcode, a Point per adres, postcode,x,y lines, or a GeoPackage")
+                        .possible_values(&["geojson", "geojson-addresses", "csv", "gpkg"])
+                        .default_value("geojson"),
+                )
+                .arg(
+                    Arg::with_name("CRS")
+                        .long("crs")
+                        .help("coordinate system of the output: 28992 (RD New) or 4326 (WGS84)")
+                        .possible_values(&["28992", "4326"])
+                        .default_value("28992"),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .help("file to write to (default: standard output, required for gpkg)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply-mutations")
+                .about("update the generated index file with a mutation extract")
+                .arg(
+                    Arg::with_name("MUTATIONS")
+                        .help("the mutation zip file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the index file, generated with --state")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                )
+                .arg(
+                    Arg::with_name("LENIENT")
+                        .long("lenient")
+                        .help("skip malformed records"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("verify")
+                .about("check the checksums of the generated index file")
+                .arg(
+                    Arg::with_name("SOURCE_DIR")
+                        .short("s")
+                        .long("source")
+                        .help("Path of the directory with the generated index file")
+                        .default_value("/home/folkertdev/Downloads/inspire"),
+                ),
+        );
 
     let matches = app.get_matches();
 
     if let Some(matches) = matches.subcommand_matches("generate") {
-        let base_dir = matches.value_of("SOURCE_DIR").unwrap();
+        let source = matches.value_of("SOURCE").unwrap();
+
+        let db_options = db_options(matches)?;
+
+        let mode = if matches.is_present("LENIENT") {
+            Mode::Lenient
+        } else {
+            Mode::Strict
+        };
+
+        let layout = if matches.is_present("SPARSE") {
+            PostcodeLayout::Sparse
+        } else {
+            PostcodeLayout::Dense
+        };
+
+        let join = match matches.value_of("JOIN").unwrap() {
+            "sort-merge" => join::Method::SortMerge,
+            _ => join::Method::HashMap,
+        };
+
+        let statuses = match matches.values_of("STATUS") {
+            None => status::Statuses::DEFAULT,
+            Some(names) => status::Statuses::from_names(names).map_err(|message| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+            })?,
+        };
+
+        let gpkg = matches.value_of("GPKG").map(PathBuf::from);
+        let geoparquet = matches.value_of("GEOPARQUET").map(PathBuf::from);
+
+        let update = match matches.value_of("UPDATE") {
+            None => None,
+            Some("index") => Some(UpdateFrom::Index),
+            Some(_) => Some(UpdateFrom::Table),
+        };
 
-        let db_credentials = DbCredentials {
-            host: matches.value_of("HOST").unwrap().to_string(),
-            user: matches.value_of("USER").unwrap().to_string(),
-            password: matches.value_of("PASSWORD").unwrap().to_string(),
-            dbname: matches.value_of("DBNAME").unwrap().to_string(),
+        let options = GenerateOptions {
+            mode,
+            layout,
+            join,
+            statuses,
+            state: matches.is_present("STATE"),
+            gpkg,
+            geoparquet,
+            update,
         };
 
         let debug = matches!(std::env::var("DEBUG"), Ok(string) if string == "1");
 
         println!("Starting bag extraction with debug = {:?}", &debug);
 
+        let source = PathBuf::from(source);
+
         if debug {
-            parse_and_db_debug(&PathBuf::from(base_dir), &db_credentials)
+            parse_and_db_debug(&source, &options, &db_options)
+        } else if matches.is_present("MILLIMETERS") {
+            parse_and_db::<MillimeterPoint>(&source, &options, &db_options)
         } else {
-            parse_and_db(&PathBuf::from(base_dir), &db_credentials)
+            parse_and_db::<Point>(&source, &options, &db_options)
         }
-    } else {
-        unreachable!("invalid subcommand. Expected on of `generate`")
-    }
-}
+    } else if let (name @ ("query" | "nearest" | "geocode" | "reverse"), Some(matches)) =
+        matches.subcommand()
+    {
+        let base_dir = matches.value_of("SOURCE_DIR").unwrap();
+
+        let index_file = IndexFile::open(PathBuf::from(base_dir).join(INDEX_FILE))?;
+
+        if index_file.coordinates() == MillimeterPoint::KIND {
+            let index = query::Index::<MillimeterPoint>::from_index_file(&index_file)?;
+            run_query(&index, name, matches)
+        } else {
+            let index = query::Index::<Point>::from_index_file(&index_file)?;
+            run_query(&index, name, matches)
+        }
+    } else if let Some(matches) = matches.subcommand_matches("export") {
+        let base_dir = matches.value_of("SOURCE_DIR").unwrap();
 
-fn parse_points_per_postcode(base_path: &Path) -> std::io::Result<Vec<Vec<Point>>> {
-    let verblijfsobjecten_path = base_path.join("vbo.zip");
-    let nummeraanduidingen_path = base_path.join("num.zip");
+        let index_file = IndexFile::open(PathBuf::from(base_dir).join(INDEX_FILE))?;
 
-    println!("num.zip location: {:?}", &nummeraanduidingen_path);
-    println!("vbo.zip location: {:?}", &verblijfsobjecten_path);
+        if index_file.coordinates() == MillimeterPoint::KIND {
+            export::<MillimeterPoint>(&index_file, matches)
+        } else {
+            export::<Point>(&index_file, matches)
+        }
+    } else if let Some(matches) = matches.subcommand_matches("apply-mutations") {
+        let base_dir = PathBuf::from(matches.value_of("SOURCE_DIR").unwrap());
+        let mutations = PathBuf::from(matches.value_of("MUTATIONS").unwrap());
 
-    let mut points_per_postcode = vec![Vec::new(); 1 << 24];
+        let mode = if matches.is_present("LENIENT") {
+            Mode::Lenient
+        } else {
+            Mode::Strict
+        };
 
-    let vs = parse_vbo::parse(&verblijfsobjecten_path);
-    let ns = parse_num::parse(&nummeraanduidingen_path);
+        let index_file = IndexFile::open(base_dir.join(INDEX_FILE))?;
 
-    match (vs, ns) {
-        (Ok(verblijfsobjecten), Ok(nummeraanduidingen)) => {
-            let it = nummeraanduidingen
-                .identificatie
-                .into_iter()
-                .zip(nummeraanduidingen.postcodes.into_iter());
-            let map: HashMap<u64, Postcode> = it.collect();
+        // the index file is overwritten, so read its settings before the mapping is dropped
+        if index_file.coordinates() == MillimeterPoint::KIND {
+            let layout = Points::<MillimeterPoint>::from_index_file(&index_file)?.layout();
+            drop(index_file);
+            apply_mutations::<MillimeterPoint>(&base_dir, &mutations, mode, layout)
+        } else {
+            let layout = Points::<Point>::from_index_file(&index_file)?.layout();
+            drop(index_file);
+            apply_mutations::<Point>(&base_dir, &mutations, mode, layout)
+        }
+    } else if let Some(matches) = matches.subcommand_matches("verify") {
+        let base_dir = matches.value_of("SOURCE_DIR").unwrap();
 
-            let it = verblijfsobjecten
-                .postcode_id
-                .into_iter()
-                .zip(verblijfsobjecten.points.into_iter());
+        let index_file = IndexFile::open(PathBuf::from(base_dir).join(INDEX_FILE))?;
+        index_file.verify()?;
 
-            for (id, point) in it {
-                match map.get(&id) {
-                    None => {}
-                    Some(postcode) => {
-                        let index = postcode.as_u32() as usize;
+        match index_file.extract_date() {
+            None => println!("extract date: unknown"),
+            Some(date) => println!("extract date: {}", date),
+        }
 
-                        points_per_postcode[index].push(point);
-                    }
+        for section in index_file.sections() {
+            println!("{}\t{} bytes", section.name, section.length);
+        }
+
+        Ok(())
+    } else {
+        unreachable!(
+            "invalid subcommand. Expected one of `generate`, `query`, `nearest`, `geocode`, `reverse`, `export`, `apply-mutations`, `verify`"
+        )
+    }
+}
+
+/// Answer the `query`, `nearest`, `geocode` or `reverse` subcommand
+fn run_query<P: Coordinates>(
+    index: &query::Index<P>,
+    name: &str,
+    matches: &clap::ArgMatches,
+) -> Result<(), Error> {
+    match name {
+        "query" => {
+            let target = parse_target(matches)?;
+            let radius = parse_arg(matches, "RADIUS")?;
+
+            for postcode in index.within_radius(target, radius) {
+                println!("{}", postcode);
+            }
+        }
+        "nearest" => {
+            let target = parse_target(matches)?;
+            let count = parse_arg(matches, "COUNT")?;
+
+            for (postcode, distance) in index.nearest_postcodes(target, count) {
+                println!("{}\t{:.1}", postcode, distance);
+            }
+        }
+        "geocode" => {
+            let invalid = |name: &str| {
+                let message = format!("invalid {}", name);
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+            };
+
+            let postcode = Postcode::try_from(matches.value_of("POSTCODE").unwrap())
+                .map_err(|_| invalid("postcode"))?;
+            let huisnummer = parse_arg(matches, "HUISNUMMER")?;
+            let huisletter = match matches.value_of("HUISLETTER") {
+                None => None,
+                Some(letter) if letter.len() == 1 => letter.bytes().next(),
+                Some(_) => return Err(invalid("huisletter").into()),
+            };
+            let toevoeging = match matches.value_of("TOEVOEGING") {
+                None => None,
+                Some(toevoeging) => {
+                    Some(Toevoeging::try_from(toevoeging).map_err(|_| invalid("toevoeging"))?)
                 }
+            };
+
+            match index.geocode(postcode, huisnummer, huisletter, toevoeging) {
+                None => println!("not found"),
+                Some(point) => match matches.value_of("CRS").unwrap() {
+                    "4326" => {
+                        let point = Wgs84Point::from(point.to_rd());
+                        println!("{} {}", point.lon, point.lat)
+                    }
+                    _ => {
+                        let point = point.to_rd();
+                        println!("{} {}", point.x, point.y)
+                    }
+                },
             }
         }
-        (Err(e), _) => panic!("Error in verblijfsobjecten {:?}", e),
-        (_, Err(e)) => panic!("Error in nummeraanduidingen {:?}", e),
+        "reverse" => {
+            let target = parse_target(matches)?;
+
+            match index.reverse_geocode(target) {
+                None => println!("not found"),
+                Some((entry, distance)) => println!(
+                    "{} {}\t{:.1}",
+                    entry.postcode(),
+                    entry.huisnummer(),
+                    distance
+                ),
+            }
+        }
+        _ => unreachable!("not a query subcommand: {}", name),
     }
 
-    println!(
-        "got points_per_postcode, length = {}",
-        points_per_postcode.len()
-    );
-
-    Ok(points_per_postcode)
+    Ok(())
 }
 
-fn parse_and_db(base_path: &Path, db_credentials: &DbCredentials) -> std::io::Result<()> {
-    let points_per_postcode = parse_points_per_postcode(base_path)?;
-    let it = points_per_postcode
-        .iter()
-        .enumerate()
-        .map(|(i, points)| (Postcode::from_index(i), points.as_slice()))
-        .skip(Postcode::MIN.as_index());
+/// Answer the `export` subcommand
+fn export<P: Coordinates>(index_file: &IndexFile, matches: &clap::ArgMatches) -> Result<(), Error> {
+    let crs = match matches.value_of("CRS").unwrap() {
+        "4326" => Crs::Wgs84,
+        _ => Crs::Rd,
+    };
+
+    let format = match matches.value_of("FORMAT").unwrap() {
+        "geojson-addresses" => export::Format::GeoJsonAddresses,
+        "csv" => export::Format::Csv,
+        "gpkg" => export::Format::GeoPackage,
+        _ => export::Format::GeoJson,
+    };
+
+    if format == export::Format::GeoPackage {
+        let path = matches.value_of("OUTPUT").ok_or_else(|| {
+            let message = "a GeoPackage needs an --output file";
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+        })?;
+
+        let addresses = Addresses::<P>::from_index_file(index_file)?;
+        let it = addresses
+            .as_slice()
+            .iter()
+            .map(|entry| (entry.postcode(), entry.huisnummer(), entry.point.to_rd()));
+
+        gpkg::write_gpkg(Path::new(path), crs, it)?;
+
+        return Ok(());
+    }
 
-    populate_database(db_credentials, it)?;
+    let writer: Box<dyn std::io::Write> = match matches.value_of("OUTPUT") {
+        None => Box::new(std::io::stdout().lock()),
+        Some(path) => Box::new(std::fs::File::create(path)?),
+    };
+
+    let mut writer = std::io::BufWriter::new(writer);
+
+    match format {
+        export::Format::GeoJson => {
+            let points = Points::<P>::from_index_file(index_file)?;
+            export::write_geojson(points.iterate_postcodes(), crs, &mut writer)?;
+        }
+        export::Format::GeoJsonAddresses => {
+            let addresses = Addresses::<P>::from_index_file(index_file)?;
+            export::write_geojson_addresses(addresses.as_slice(), crs, &mut writer)?;
+        }
+        export::Format::Csv => {
+            let points = Points::<P>::from_index_file(index_file)?;
+            export::write_csv(points.iterate_postcodes(), crs, &mut writer)?;
+        }
+        export::Format::GeoPackage => unreachable!("a GeoPackage is not written as a stream"),
+    }
+
+    std::io::Write::flush(&mut writer)?;
 
     Ok(())
 }
 
-/// Parse the VBO and NUM zip files, extract the relevant data, and persist it to disk
-fn parse_and_db_debug(base_path: &Path, db_credentials: &DbCredentials) -> std::io::Result<()> {
-    if false {
-        let points_per_postcode = parse_points_per_postcode(base_path)?;
+/// The target point of a query, converted to RD New
+fn parse_target(matches: &clap::ArgMatches) -> std::io::Result<RdPoint> {
+    let a = parse_arg(matches, "X")?;
+    let b = parse_arg(matches, "Y")?;
 
-        Points::create_files(
-            base_path.join("points-28992.bin"),
-            base_path.join("slices-28992.bin"),
-            points_per_postcode,
-        )?;
+    match matches.value_of("CRS").unwrap() {
+        "4326" => Ok(Wgs84Point::new(a, b).into()),
+        _ => Ok(RdPoint::new(a, b)),
     }
+}
 
-    let points_per_postcode = Points::from_files(
-        base_path.join("points-28992.bin"),
-        base_path.join("slices-28992.bin"),
-    )?;
+fn parse_arg<T>(matches: &clap::ArgMatches, name: &str) -> std::io::Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = matches.value_of(name).unwrap();
+
+    value.parse().map_err(|e| {
+        let message = format!("invalid value {:?} for {}: {}", value, name, e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+    })
+}
 
-    let it = points_per_postcode.iterate_postcodes();
+/// Parse the VBO and NUM zip files, and join every Verblijfsobject with its Nummeraanduiding
+///
+/// `source` is either the national extract, or a directory with `vbo.zip` and `num.zip`. The
+/// report contains the records that were skipped by the parsers, the status filter and the join.
+/// With `--state`, the voorkomens that are kept now or later are also written to the state file for
+/// `apply-mutations`.
+fn parse_addresses(
+    source: &Path,
+    options: &GenerateOptions,
+) -> Result<(Vec<Adres>, Report), Error> {
+    let (verblijfsobjecten, nummeraanduidingen) =
+        ZipSource::verblijfsobjecten_and_nummeraanduidingen(source)?;
+
+    println!("num.zip location: {}", &nummeraanduidingen);
+    println!("vbo.zip location: {}", &verblijfsobjecten);
+
+    let extract_date = verblijfsobjecten.extract_date();
+
+    let vs = parse_vbo::parse(&verblijfsobjecten, options.mode);
+    let ns = parse_num::parse(&nummeraanduidingen, options.mode);
+
+    let (verblijfsobjecten, nummeraanduidingen) = match (vs, ns) {
+        (Ok(verblijfsobjecten), Ok(nummeraanduidingen)) => (verblijfsobjecten, nummeraanduidingen),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+
+    // the state also keeps the voorkomens that only start later. It only replaces the previous
+    // state once the index file that goes with it has been replaced, see `replace_state`
+    if options.state {
+        let statuses = options.statuses;
+        let state = mutations::State::new(
+            extract_date,
+            statuses,
+            &verblijfsobjecten,
+            &nummeraanduidingen,
+        );
+        let state_path = output_dir(source).join(mutations::STATE_FILE);
+        state.write(state_path.with_extension("bin.tmp"))?;
+    }
 
-    populate_database(db_credentials, it)?;
+    // only the current voorkomens, of objects with one of the selected statuses
+    let date = match extract_date {
+        Some(date) => date,
+        None => {
+            let today = status::today();
+            println!(
+                "the date of the extract is unknown, keeping the voorkomens of {}",
+                today
+            );
+            today
+        }
+    };
+    let filter = status::Filter {
+        statuses: options.statuses,
+        date,
+    };
+    let mut verblijfsobjecten = verblijfsobjecten.filter(&filter);
+    let mut nummeraanduidingen = nummeraanduidingen.filter(&filter);
+
+    let mut report = std::mem::take(&mut verblijfsobjecten.report)
+        .merge(std::mem::take(&mut nummeraanduidingen.report));
+
+    let method = options.join;
+    let addresses = join::join(method, verblijfsobjecten, nummeraanduidingen, &mut report);
+
+    println!("got addresses, length = {}", addresses.len());
+
+    for (reason, skipped) in report.iter() {
+        println!("skipped {} records: {}", skipped.count, reason);
+    }
+
+    if let Some(unreferenced) = report.get_unreferenced() {
+        let count = unreferenced.count;
+        println!(
+            "{} Nummeraanduidingen are not the hoofdadres of a Verblijfsobject",
+            count
+        );
+    }
+
+    Ok((addresses, report))
+}
+
+/// Group the points of the addresses by postcode
+fn postcode_points<P: Coordinates>(addresses: &[Adres]) -> PostcodePoints<P> {
+    let items = addresses
+        .iter()
+        .map(|adres| (adres.postcode, P::from_rd(adres.point.to_rd())));
+
+    PostcodePoints::new(items)
+}
+
+/// Persist everything that the queries need to the index file at `path`
+fn create_files<P: Coordinates>(
+    path: &Path,
+    source: &Path,
+    addresses: &[Adres],
+    postcode_points: &PostcodePoints<P>,
+    layout: PostcodeLayout,
+) -> Result<(), Error> {
+    let (verblijfsobjecten, _) = ZipSource::verblijfsobjecten_and_nummeraanduidingen(source)?;
+    let extract_date = verblijfsobjecten.extract_date();
+
+    query::Index::create_file(path, extract_date, addresses, postcode_points, layout)?;
 
     Ok(())
 }
 
-struct DbCredentials {
-    host: String,
-    user: String,
-    password: String,
-    dbname: String,
+/// The directory that the index file is written to: the source directory itself, or the
+/// directory that contains the national extract
+fn output_dir(source: &Path) -> PathBuf {
+    if source.is_dir() {
+        source.to_path_buf()
+    } else {
+        source.parent().map(Path::to_path_buf).unwrap_or_default()
+    }
 }
 
-fn populate_database<'a, I>(db_credentials: &DbCredentials, data: I) -> std::io::Result<()>
-where
-    I: Iterator<Item = (Postcode, &'a [Point])>,
-{
-    use postgres::{Client, NoTls};
+/// The options of the `generate` subcommand
+struct GenerateOptions {
+    mode: Mode,
+    layout: PostcodeLayout,
+    join: join::Method,
+    /// the statuses of the objects to keep
+    statuses: status::Statuses,
+    /// also write the state that `apply-mutations` needs
+    state: bool,
+    /// write a GeoPackage instead of populating the database
+    gpkg: Option<PathBuf>,
+    /// also write a GeoParquet file
+    geoparquet: Option<PathBuf>,
+    /// apply only the changes to the database, instead of reloading it
+    update: Option<UpdateFrom>,
+}
 
-    let arguments = &format!(
-        "host={} user={} password={} dbname={}",
-        db_credentials.host, db_credentials.user, db_credentials.password, db_credentials.dbname
-    );
+/// What `generate --update` compares the new points with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UpdateFrom {
+    /// the current contents of the table
+    Table,
+    /// the index file of the previous run, which the table was loaded from
+    Index,
+}
 
-    let mut client = Client::connect(arguments, NoTls).unwrap();
+fn parse_and_db<P: Coordinates>(
+    source: &Path,
+    options: &GenerateOptions,
+    db_options: &DbOptions,
+) -> Result<(), Error> {
+    let (addresses, report) = parse_addresses(source, options)?;
+    let postcode_points = postcode_points::<P>(&addresses);
+
+    let previous = match options.update {
+        Some(UpdateFrom::Index) => Some(previous_rows::<P>(source, db_options.target.crs)?),
+        _ => None,
+    };
+
+    // `--update index` relies on the index file describing what is in the database, so when the
+    // database is loaded, the new index file only replaces the previous one once that succeeded
+    let index_path = output_dir(source).join(INDEX_FILE);
+    let pending_path = match options.gpkg {
+        Some(_) => index_path.clone(),
+        None => index_path.with_extension("bin.tmp"),
+    };
+
+    create_files(
+        &pending_path,
+        source,
+        &addresses,
+        &postcode_points,
+        options.layout,
+    )?;
 
-    println!("Inserting data into adressen_28992");
+    if options.mode == Mode::Lenient {
+        report.write_json_file(output_dir(source).join(report::REPORT_FILE))?;
+    }
 
-    let mut writer = client.copy_in("COPY adressen_28992 FROM stdin").unwrap();
+    if let Some(path) = &options.geoparquet {
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let row_group_size = geoparquet::ROW_GROUP_SIZE;
 
-    for (postcode, points) in data {
-        if postcode > Postcode::MAX {
-            break;
-        }
+        let count = geoparquet::write_geoparquet(&addresses, Crs::Rd, row_group_size, file)?;
+        println!("Wrote {} addresses to {}", count, path.display());
+    }
 
-        for point in points.iter() {
-            {
-                use std::io::Write;
+    match &options.gpkg {
+        Some(path) => {
+            let it = addresses
+                .iter()
+                .map(|adres| (adres.postcode, adres.huisnummer, adres.point.to_rd()));
 
-                writeln!(writer, "POINT({} {})\t{}", point.x, point.y, postcode)?;
+            let count = gpkg::write_gpkg(path, Crs::Rd, it)?;
+            println!("Wrote {} addresses to {}", count, path.display());
+        }
+        None => {
+            match options.update {
+                None => populate_database(db_options, postcode_points.iter())?,
+                Some(_) => update_database(db_options, previous, postcode_points.iter())?,
             }
+
+            std::fs::rename(&pending_path, &index_path)?;
         }
     }
 
-    let rows_written = writer.finish().unwrap();
+    replace_state(source, options)
+}
 
-    println!("Done inserting data, inserted {} rows", rows_written);
+/// Replace the state file with the one that `parse_addresses` wrote, if `--state` was given
+///
+/// `apply-mutations` rebuilds the index file from the state file, so the two must describe the
+/// same extract: this is only called once the index file has been replaced.
+fn replace_state(source: &Path, options: &GenerateOptions) -> Result<(), Error> {
+    if options.state {
+        let state_path = output_dir(source).join(mutations::STATE_FILE);
+        std::fs::rename(state_path.with_extension("bin.tmp"), state_path)?;
+    }
 
     Ok(())
 }
 
-/// Turn a `&[T]` into a `&[u8]` and write it to a file. Clearly that only works
-/// if a value of type `T` is fully represented by its bytes (e.g. no heap allocations)
-fn write_slice_to_file<P, T: Copy>(path: P, slice: &[T]) -> std::io::Result<()>
-where
-    P: AsRef<Path>,
-{
-    let ptr = slice.as_ptr();
-    let byte_width = slice.len() * std::mem::size_of::<T>();
+/// The rows that the previous index file would have loaded into the database
+///
+/// The rows are compared exactly, so the previous index file must store the same kind of
+/// coordinates as this run, or every row would differ.
+fn previous_rows<P: Coordinates>(source: &Path, crs: Crs) -> Result<Vec<database::Row>, Error> {
+    let path = output_dir(source).join(INDEX_FILE);
+    let index_file = IndexFile::open(&path)?;
+
+    if index_file.coordinates() != P::KIND {
+        let message = format!(
+            "{} stores other coordinates than this run (kind {}, expected {}), use --update table",
+            path.display(),
+            index_file.coordinates(),
+            P::KIND
+        );
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, message).into());
+    }
 
-    let bytes: &[u8] = unsafe { std::slice::from_raw_parts(ptr as *const _, byte_width) };
+    let points = Points::<P>::from_index_file(&index_file)?;
+    let it = points
+        .iterate_postcodes()
+        .flat_map(|(postcode, points)| points.iter().map(move |point| (postcode, point.to_rd())));
 
-    std::fs::write(path, bytes)
+    Ok(database::project(crs, it).collect())
 }
 
-struct Points {
-    points: MemoryMappedSlice<Point>,
-    slices: MemoryMappedSlice<(u32, u32)>,
+/// Apply the mutations to the state file in `base_dir`, and rebuild the index file from it
+fn apply_mutations<P: Coordinates>(
+    base_dir: &Path,
+    mutations: &Path,
+    mode: Mode,
+    layout: PostcodeLayout,
+) -> Result<(), Error> {
+    let state_path = base_dir.join(mutations::STATE_FILE);
+    let mut state = mutations::State::read(&state_path)?;
+
+    let source = ZipSource::mutations(mutations)?;
+    println!("mutations location: {}", &source);
+
+    let period = source.mutation_period().ok_or_else(|| {
+        let message = format!("{} is not named like 9999MUTddmmyyyy-ddmmyyyy.zip", source);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+    })?;
+
+    let vs = parse_vbo::parse(&source, mode);
+    let ns = parse_num::parse(&source, mode);
+
+    let (mut verblijfsobjecten, mut nummeraanduidingen) = match (vs, ns) {
+        (Ok(verblijfsobjecten), Ok(nummeraanduidingen)) => (verblijfsobjecten, nummeraanduidingen),
+        (Err(e), _) | (_, Err(e)) => return Err(e),
+    };
+
+    println!(
+        "got {} Verblijfsobjecten and {} Nummeraanduidingen",
+        verblijfsobjecten.identificatie.len(),
+        nummeraanduidingen.identificatie.len()
+    );
+
+    let report = std::mem::take(&mut verblijfsobjecten.report)
+        .merge(std::mem::take(&mut nummeraanduidingen.report));
+
+    for (reason, skipped) in report.iter() {
+        println!("skipped {} records: {}", skipped.count, reason);
+    }
+
+    state.apply(period, &verblijfsobjecten, &nummeraanduidingen)?;
+
+    let (verblijfsobjecten, nummeraanduidingen) = state.to_parsed();
+    let mut report = Report::default();
+    let method = join::Method::SortMerge;
+    let addresses = join::join(method, verblijfsobjecten, nummeraanduidingen, &mut report);
+
+    println!("got addresses, length = {}", addresses.len());
+
+    let postcode_points = postcode_points::<P>(&addresses);
+    let index_path = base_dir.join(INDEX_FILE);
+
+    // both files are complete before either replaces the previous one. The state goes first:
+    // should the index then not be replaced, the next mutations rebuild it from the state.
+    let pending_state = state_path.with_extension("bin.tmp");
+    let pending_index = index_path.with_extension("bin.tmp");
+
+    state.write(&pending_state)?;
+    query::Index::create_file(
+        &pending_index,
+        state.extract_date,
+        &addresses,
+        &postcode_points,
+        layout,
+    )?;
+
+    std::fs::rename(&pending_state, &state_path)?;
+    std::fs::rename(&pending_index, &index_path)?;
+
+    Ok(())
 }
 
-impl Points {
-    fn from_files<P>(points_path: P, slices_path: P) -> std::io::Result<Self>
-    where
-        P: AsRef<Path>,
-    {
-        let index = Self {
-            points: MemoryMappedSlice::from_file(points_path)?,
-            slices: MemoryMappedSlice::from_file(slices_path)?,
-        };
+/// Parse the VBO and NUM zip files, extract the relevant data, and persist it to disk
+fn parse_and_db_debug(
+    source: &Path,
+    options: &GenerateOptions,
+    db_options: &DbOptions,
+) -> Result<(), Error> {
+    let base_path = output_dir(source);
 
-        Ok(index)
+    if false {
+        let (addresses, _) = parse_addresses(source, options)?;
+        let postcode_points = postcode_points::<Point>(&addresses);
+
+        let path = base_path.join(INDEX_FILE);
+        create_files(&path, source, &addresses, &postcode_points, options.layout)?;
+        replace_state(source, options)?;
     }
 
-    fn create_files<P>(
-        points_path: P,
-        slices_path: P,
-        points_per_postcode: Vec<Vec<Point>>,
-    ) -> std::io::Result<()>
-    where
-        P: AsRef<Path>,
-    {
-        let mut points = Vec::with_capacity(700_000);
-        let mut slices = Vec::with_capacity(1 << 24);
+    let index_file = IndexFile::open(base_path.join(INDEX_FILE))?;
 
-        for points_with_postcode in points_per_postcode.iter() {
-            let start = points.len();
-            let length = points_with_postcode.len();
+    if index_file.coordinates() == MillimeterPoint::KIND {
+        let points_per_postcode = Points::<MillimeterPoint>::from_index_file(&index_file)?;
+        populate_database(db_options, points_per_postcode.iterate_postcodes())?;
+    } else {
+        let points_per_postcode = Points::<Point>::from_index_file(&index_file)?;
+        populate_database(db_options, points_per_postcode.iterate_postcodes())?;
+    }
 
-            points.extend(points_with_postcode.iter().copied());
+    Ok(())
+}
 
-            slices.push((start as u32, length as u32));
-        }
+/// Where `generate` loads the points
+struct DbOptions {
+    connection: database::Connection,
+    target: database::Target,
+}
 
-        write_slice_to_file(points_path, &points)?;
-        write_slice_to_file(slices_path, &slices)?;
+/// The connection from `--database-url` or the separate flags, completed with the `PG*`
+/// environment variables
+fn db_options(matches: &clap::ArgMatches) -> Result<DbOptions, Error> {
+    let (mut config, mut tls) = match matches.value_of("DATABASE_URL") {
+        None => (postgres::Config::new(), None),
+        Some(string) => database::parse_connection_string(string)?,
+    };
 
-        Ok(())
+    if let Some(host) = matches.value_of("HOST") {
+        config.host(host);
+    }
+    if let Some(user) = matches.value_of("USER") {
+        config.user(user);
+    }
+    if let Some(password) = matches.value_of("PASSWORD") {
+        config.password(password);
+    }
+    if let Some(dbname) = matches.value_of("DBNAME") {
+        config.dbname(dbname);
+    }
+    if matches.is_present("TLS") {
+        tls = Some(database::TlsMode::Require);
     }
 
-    fn iterate_postcodes(&self) -> impl Iterator<Item = (Postcode, &[Point])> {
-        let slices = self.slices.as_slice();
-        let points = self.points.as_slice();
+    let var = |name: &str| std::env::var(name).ok();
+    let mut connection = database::Connection::new(config, tls, var)?;
 
-        (0..(1 << 24)).map(|index| {
-            let postcode = Postcode::from_index(index);
+    if connection.config.get_hosts().is_empty() {
+        connection.config.host("localhost");
+    }
+    if let Some(path) = matches.value_of("SSL_ROOT_CERT") {
+        connection.root_certificate = Some(PathBuf::from(path));
+    }
 
-            let (start, length) = slices[index];
+    let crs = match matches.value_of("SRID").unwrap() {
+        "4326" => Crs::Wgs84,
+        _ => Crs::Rd,
+    };
 
-            (postcode, &points[start as usize..][..length as usize])
-        })
+    let mut target = database::Target::new(crs);
+    target.schema = matches.value_of("SCHEMA").unwrap().to_string();
+    if let Some(table) = matches.value_of("TABLE") {
+        target.table = table.to_string();
     }
+
+    Ok(DbOptions { connection, target })
+}
+
+fn populate_database<'a, P, I>(db_options: &DbOptions, data: I) -> Result<(), Error>
+where
+    P: Coordinates,
+    I: Iterator<Item = (Postcode, &'a [P])>,
+{
+    let mut client = db_options.connection.connect()?;
+
+    let points = data
+        .flat_map(|(postcode, points)| points.iter().map(move |point| (postcode, point.to_rd())));
+
+    let rows = database::project(db_options.target.crs, points);
+    database::load(&mut client, &db_options.target, rows)?;
+
+    Ok(())
+}
+
+/// Apply only the differences between `previous` (or, if that is `None`, the current contents of
+/// the table) and `data` to the database
+fn update_database<'a, P, I>(
+    db_options: &DbOptions,
+    previous: Option<Vec<database::Row>>,
+    data: I,
+) -> Result<(), Error>
+where
+    P: Coordinates,
+    I: Iterator<Item = (Postcode, &'a [P])>,
+{
+    let mut client = db_options.connection.connect()?;
+
+    let previous = match previous {
+        Some(rows) => rows,
+        None => database::read_rows(&mut client, &db_options.target)?,
+    };
+
+    let points = data
+        .flat_map(|(postcode, points)| points.iter().map(move |point| (postcode, point.to_rd())));
+    let rows = database::project(db_options.target.crs, points).collect();
+
+    let changes = database::Changes::between(previous, rows);
+
+    if changes.is_empty() {
+        println!("The database is up to date");
+        return Ok(());
+    }
+
+    database::update(&mut client, &db_options.target, &changes)
 }
 
 #[cfg(test)]
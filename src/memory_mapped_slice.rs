@@ -7,34 +7,138 @@ use std::path::Path;
 // memory: loading is instantaneous.
 //
 // That of course only works if the values in the slice are represented fully by their bytes
-// (no pointers/references)
+// (no pointers/references). The `Pod` trait marks the types for which that is the case.
 
-pub struct MemoryMappedSlice<T> {
-    mmap: memmap::Mmap,
+/// Plain old data: a type that is fully represented by its bytes
+///
+/// # Safety
+///
+/// Only implement this for types that
+///
+/// - are `Copy` and contain no pointers or references (so also no `Vec`, `String`, ...)
+/// - have no padding bytes, so every byte of a value is initialized
+/// - are valid for every bit pattern (e.g. no `bool` or enums)
+/// - have a size that is not zero
+///
+/// For structs, that means `#[repr(C)]` with fields that are `Pod` themselves, and explicit
+/// padding fields where needed.
+pub unsafe trait Pod: Copy + 'static {}
+
+unsafe impl Pod for u8 {}
+unsafe impl Pod for u32 {}
+unsafe impl Pod for u64 {}
+unsafe impl Pod for f32 {}
+
+/// A part of another array: `length` elements from `start`, e.g. the points of one postcode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct Range {
+    pub start: u32,
+    pub length: u32,
+}
+
+// two `u32` fields, so there is no padding
+unsafe impl Pod for Range {}
+
+impl Range {
+    pub const fn new(start: u32, length: u32) -> Self {
+        Self { start, length }
+    }
+
+    /// The elements of `slice` in this range
+    pub fn of<T>(self, slice: &[T]) -> &[T] {
+        &slice[self.start as usize..][..self.length as usize]
+    }
+}
+
+/// View `slice` as its bytes
+pub fn as_bytes<T: Pod>(slice: &[T]) -> &[u8] {
+    let ptr = slice.as_ptr();
+    let byte_width = std::mem::size_of_val(slice);
+
+    // safe because `T: Pod` has no padding, so every byte is initialized
+    unsafe { std::slice::from_raw_parts(ptr as *const u8, byte_width) }
+}
+
+pub struct MemoryMappedSlice<T: Pod> {
+    /// `None` for an empty file: those cannot be memory mapped
+    mmap: Option<memmap::Mmap>,
     _marker: core::marker::PhantomData<T>,
 }
 
-impl<T> MemoryMappedSlice<T> {
+impl<T: Pod> MemoryMappedSlice<T> {
     pub fn from_file<P>(bin_path: P) -> std::io::Result<Self>
     where
         P: AsRef<Path>,
     {
         let file = std::fs::File::open(bin_path)?;
 
-        let index = Self {
-            mmap: unsafe { memmap::Mmap::map(&file)? },
+        let mmap = if file.metadata()?.len() == 0 {
+            None
+        } else {
+            Some(unsafe { memmap::Mmap::map(&file)? })
+        };
+
+        Self::new(mmap)
+    }
+
+    /// Map `length` bytes of `file`, starting at `offset`
+    pub fn from_section(file: &std::fs::File, offset: u64, length: u64) -> std::io::Result<Self> {
+        let mmap = if length == 0 {
+            None
+        } else {
+            let mut options = memmap::MmapOptions::new();
+            options.offset(offset).len(length as usize);
+
+            Some(unsafe { options.map(file)? })
+        };
+
+        Self::new(mmap)
+    }
+
+    /// Check that the mapped bytes can be viewed as a `&[T]`
+    fn new(mmap: Option<memmap::Mmap>) -> std::io::Result<Self> {
+        let invalid =
+            |message: String| std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+
+        let size = std::mem::size_of::<T>();
+        let align = std::mem::align_of::<T>();
+
+        if let Some(bytes) = &mmap {
+            if !bytes.len().is_multiple_of(size) {
+                let message = format!(
+                    "{} bytes is not a multiple of the element size {}",
+                    bytes.len(),
+                    size
+                );
+                return Err(invalid(message));
+            }
+
+            if !(bytes.as_ptr() as usize).is_multiple_of(align) {
+                let message = format!("mapped bytes are not aligned to {} bytes", align);
+                return Err(invalid(message));
+            }
+        }
+
+        let slice = Self {
+            mmap,
             _marker: core::marker::PhantomData,
         };
 
-        Ok(index)
+        Ok(slice)
     }
 
     pub fn as_slice(&self) -> &[T] {
-        let slice: &[u8] = &self.mmap;
+        let slice: &[u8] = match &self.mmap {
+            None => return &[],
+            Some(mmap) => mmap,
+        };
         let element_width = slice.len() / std::mem::size_of::<T>();
         let ptr = slice.as_ptr();
 
-        unsafe { std::slice::from_raw_parts(ptr as *const _, element_width) }
+        // safe because `new` checked the length and alignment, and `T: Pod` is valid for any
+        // bit pattern
+        unsafe { std::slice::from_raw_parts(ptr as *const T, element_width) }
     }
 
     pub fn len(&self) -> usize {
@@ -45,3 +149,25 @@ impl<T> MemoryMappedSlice<T> {
         self.as_slice().is_empty()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn validated() {
+        let path = std::env::temp_dir().join("bagextract-memory-mapped-slice.bin");
+        std::fs::write(&path, as_bytes(&[1u32, 2, 3])).unwrap();
+
+        let slice = MemoryMappedSlice::<u32>::from_file(&path).unwrap();
+        assert_eq!(&[1, 2, 3], slice.as_slice());
+
+        // 12 bytes are not a whole number of u64's
+        assert!(MemoryMappedSlice::<u64>::from_file(&path).is_err());
+
+        // an offset of 1 is not aligned for u32
+        let file = std::fs::File::open(&path).unwrap();
+        assert!(MemoryMappedSlice::<u32>::from_section(&file, 1, 8).is_err());
+        assert!(MemoryMappedSlice::<u8>::from_section(&file, 1, 8).is_ok());
+    }
+}
@@ -0,0 +1,785 @@
+// Update the index with the daily mutations, without parsing the full extract again
+//
+// `generate --state` also stores the voorkomens of the Verblijfsobjecten and Nummeraanduidingen
+// that it may need (see `crate::status`) in `state.bin` (in the format of the index file, see
+// `crate::index_file`): those with a kept status that have not ended, so both the current ones and
+// the ones that only start later. Each with its validity period.
+//
+// A mutation zip contains the same records as the full extract, in a `toevoeging` (a new
+// voorkomen) or a `wijziging` (a voorkomen before, `was`, and after, `wordt`, usually because it
+// got an end). The parsers skip the `was` states. Every voorkomen in the mutations replaces the
+// stored one, or removes it when it is no longer needed (e.g. it ended, or was corrected). Then
+// the stored voorkomens that ended by the date of the mutations are dropped, and the index is
+// rebuilt from the ones that are current on that date. So a voorkomen whose begin or end was
+// already known takes effect on its date, even if no later mutation mentions it. The mutation
+// files must be applied in order: each one must start at the date of the state.
+//
+// That is a join and a sort of the stored state, instead of parsing 2 GB of xml. Because the
+// join puts the addresses in a canonical order (see `crate::join`), the result is the same index
+// file as `generate` writes for the full extract of the date of the mutations.
+
+use std::path::Path;
+
+use crate::adres::{Huisnummer, Toevoeging};
+use crate::error::Error;
+use crate::index_file::{IndexFile, IndexFileWriter};
+use crate::memory_mapped_slice::Pod;
+use crate::parse_num::Postcodes;
+use crate::parse_vbo::Verblijfsobjecten;
+use crate::point::{Coordinates, MillimeterPoint};
+use crate::postcode::Postcode;
+use crate::report::Report;
+use crate::status::{self, Filter, Status, Statuses, Voorkomen};
+
+pub const STATE_FILE: &str = "state.bin";
+
+const VERBLIJFSOBJECTEN_SECTION: &str = "verblijfsobjecten";
+const NUMMERAANDUIDINGEN_SECTION: &str = "nummeraanduidingen";
+const STATUSES_SECTION: &str = "statuses";
+
+/// A voorkomen of a Verblijfsobject as it is stored in the state file
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+struct VerblijfsobjectEntry {
+    identificatie: u64,
+    hoofdadres: u64,
+    point: MillimeterPoint,
+    voorkomen: u32,
+    /// `yyyymmdd`, or 0 when there is none
+    begin_geldigheid: u32,
+    /// `yyyymmdd`, or 0 when there is none
+    eind_geldigheid: u32,
+    /// index in `Status::ALL`
+    status: u8,
+    _padding: [u8; 3],
+}
+
+// the padding is explicit, so every byte is part of a field
+unsafe impl Pod for VerblijfsobjectEntry {}
+const _: () = assert!(std::mem::size_of::<VerblijfsobjectEntry>() == 40);
+
+/// A voorkomen of a Nummeraanduiding as it is stored in the state file. The huisnummer is encoded
+/// like in `crate::geocode::AddressEntry`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+struct NummeraanduidingEntry {
+    identificatie: u64,
+    postcode: u32,
+    nummer: u32,
+    toevoeging: [u8; 4],
+    voorkomen: u32,
+    /// `yyyymmdd`, or 0 when there is none
+    begin_geldigheid: u32,
+    /// `yyyymmdd`, or 0 when there is none
+    eind_geldigheid: u32,
+    letter: u8,
+    /// index in `Status::ALL`
+    status: u8,
+    _padding: [u8; 6],
+}
+
+// the padding is explicit, so every byte is part of a field
+unsafe impl Pod for NummeraanduidingEntry {}
+const _: () = assert!(std::mem::size_of::<NummeraanduidingEntry>() == 40);
+
+trait Entry: Copy {
+    /// The identificatie of the object, and of the voorkomen
+    fn key(&self) -> (u64, u32);
+    fn status(&self) -> Status;
+    fn voorkomen(&self) -> Voorkomen;
+}
+
+impl Entry for VerblijfsobjectEntry {
+    fn key(&self) -> (u64, u32) {
+        (self.identificatie, self.voorkomen)
+    }
+
+    fn status(&self) -> Status {
+        status_from_index(self.status)
+    }
+
+    fn voorkomen(&self) -> Voorkomen {
+        voorkomen_from_entry(self.voorkomen, self.begin_geldigheid, self.eind_geldigheid)
+    }
+}
+
+impl Entry for NummeraanduidingEntry {
+    fn key(&self) -> (u64, u32) {
+        (self.identificatie, self.voorkomen)
+    }
+
+    fn status(&self) -> Status {
+        status_from_index(self.status)
+    }
+
+    fn voorkomen(&self) -> Voorkomen {
+        voorkomen_from_entry(self.voorkomen, self.begin_geldigheid, self.eind_geldigheid)
+    }
+}
+
+fn status_index(status: Status) -> u8 {
+    Status::ALL.iter().position(|s| *s == status).unwrap_or(0) as u8
+}
+
+fn status_from_index(index: u8) -> Status {
+    Status::ALL
+        .get(index as usize)
+        .copied()
+        .unwrap_or(Status::ALL[0])
+}
+
+fn voorkomen_from_entry(identificatie: u32, begin: u32, eind: u32) -> Voorkomen {
+    Voorkomen {
+        identificatie,
+        begin_geldigheid: (begin != 0).then_some(begin),
+        eind_geldigheid: (eind != 0).then_some(eind),
+        eind_registratie: false,
+    }
+}
+
+/// The voorkomens of Verblijfsobjecten and Nummeraanduidingen with a kept status, that have not
+/// ended at the date of the state: the current ones, and the ones that only start later. Each
+/// sorted by the identificatie of the object and of the voorkomen.
+#[derive(Debug, PartialEq, Clone)]
+pub struct State {
+    /// the date of the extract (or the last mutations) as `yyyymmdd`
+    pub extract_date: Option<u32>,
+    /// the statuses that were kept
+    pub statuses: Statuses,
+    verblijfsobjecten: Vec<VerblijfsobjectEntry>,
+    nummeraanduidingen: Vec<NummeraanduidingEntry>,
+}
+
+impl State {
+    /// The state of the parsed (and not yet filtered) objects of the extract of `extract_date`
+    pub fn new(
+        extract_date: Option<u32>,
+        statuses: Statuses,
+        verblijfsobjecten: &Verblijfsobjecten,
+        nummeraanduidingen: &Postcodes,
+    ) -> Self {
+        let kept = |status, voorkomen: &Voorkomen| {
+            statuses.contains(status) && !voorkomen.has_ended(extract_date)
+        };
+
+        let mut state = Self {
+            extract_date,
+            statuses,
+            verblijfsobjecten: verblijfsobject_entries(verblijfsobjecten)
+                .filter(|(entry, voorkomen)| kept(entry.status(), voorkomen))
+                .map(|(entry, _)| entry)
+                .collect(),
+            nummeraanduidingen: nummeraanduiding_entries(nummeraanduidingen)
+                .filter(|(entry, voorkomen)| kept(entry.status(), voorkomen))
+                .map(|(entry, _)| entry)
+                .collect(),
+        };
+
+        // stable, so that duplicates stay in the order of the extract
+        state.verblijfsobjecten.sort_by_key(Entry::key);
+        state.nummeraanduidingen.sort_by_key(Entry::key);
+
+        state
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let file = IndexFile::open(path)?;
+
+        let verblijfsobjecten = file.section::<VerblijfsobjectEntry>(VERBLIJFSOBJECTEN_SECTION)?;
+        let nummeraanduidingen =
+            file.section::<NummeraanduidingEntry>(NUMMERAANDUIDINGEN_SECTION)?;
+        let statuses = file.section::<u32>(STATUSES_SECTION)?;
+
+        Ok(Self {
+            extract_date: file.extract_date(),
+            statuses: Statuses::from_bits(statuses.as_slice().first().copied().unwrap_or(0)),
+            verblijfsobjecten: verblijfsobjecten.as_slice().to_vec(),
+            nummeraanduidingen: nummeraanduidingen.as_slice().to_vec(),
+        })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let extract_date = self.extract_date.unwrap_or(0);
+        let mut writer = IndexFileWriter::create(path, extract_date, MillimeterPoint::KIND)?;
+
+        writer.write_section(VERBLIJFSOBJECTEN_SECTION, &self.verblijfsobjecten)?;
+        writer.write_section(NUMMERAANDUIDINGEN_SECTION, &self.nummeraanduidingen)?;
+        writer.write_section(STATUSES_SECTION, &[self.statuses.bits()])?;
+
+        writer.finish()
+    }
+
+    /// Apply the parsed (and not yet filtered) mutations of the `period` (from, to) as
+    /// `yyyymmdd`
+    ///
+    /// The mutations must start at the date of the state, so every mutation file is applied
+    /// exactly once, and in order. Every voorkomen in the mutations replaces the stored one, or
+    /// removes it when it is not kept (its status is not kept, it ended, or it was corrected).
+    /// Then the stored voorkomens that ended by the new date are dropped.
+    pub fn apply(
+        &mut self,
+        period: (u32, u32),
+        verblijfsobjecten: &Verblijfsobjecten,
+        nummeraanduidingen: &Postcodes,
+    ) -> Result<(), Error> {
+        let (from, to) = period;
+
+        if self.extract_date != Some(from) {
+            let message = match self.extract_date {
+                None => "the state has no date, so mutations cannot be applied".to_string(),
+                Some(date) => format!(
+                    "the mutations are from {} to {}, but the state is of {}",
+                    from, to, date
+                ),
+            };
+            let error = std::io::Error::new(std::io::ErrorKind::InvalidInput, message);
+            return Err(error.into());
+        }
+
+        let (statuses, date) = (self.statuses, Some(to));
+
+        let changes = verblijfsobject_entries(verblijfsobjecten)
+            .map(|(entry, voorkomen)| Change::new(statuses, date, entry, voorkomen))
+            .collect();
+        let old = std::mem::take(&mut self.verblijfsobjecten);
+        self.verblijfsobjecten = upsert(old, changes);
+
+        let changes = nummeraanduiding_entries(nummeraanduidingen)
+            .map(|(entry, voorkomen)| Change::new(statuses, date, entry, voorkomen))
+            .collect();
+        let old = std::mem::take(&mut self.nummeraanduidingen);
+        self.nummeraanduidingen = upsert(old, changes);
+
+        // a voorkomen whose end was already known
+        self.verblijfsobjecten
+            .retain(|entry| !entry.voorkomen().has_ended(date));
+        self.nummeraanduidingen
+            .retain(|entry| !entry.voorkomen().has_ended(date));
+
+        self.extract_date = date;
+
+        Ok(())
+    }
+
+    /// The voorkomens that are current at the date of the state, in the form that
+    /// `crate::join::join` takes
+    pub fn to_parsed(&self) -> (Verblijfsobjecten, Postcodes) {
+        let filter = Filter {
+            statuses: self.statuses,
+            date: self.extract_date.unwrap_or_else(status::today),
+        };
+        let objects: Vec<_> = self
+            .verblijfsobjecten
+            .iter()
+            .filter(|entry| {
+                filter
+                    .skip_reason(entry.status(), &entry.voorkomen())
+                    .is_none()
+            })
+            .collect();
+        let verblijfsobjecten = Verblijfsobjecten {
+            identificatie: objects.iter().map(|e| e.identificatie).collect(),
+            points: objects.iter().map(|e| e.point).collect(),
+            postcode_id: objects.iter().map(|e| e.hoofdadres).collect(),
+            status: objects.iter().map(|e| e.status()).collect(),
+            voorkomens: objects.iter().map(|e| e.voorkomen()).collect(),
+            report: Report::default(),
+        };
+
+        let numbers: Vec<_> = self
+            .nummeraanduidingen
+            .iter()
+            .filter(|entry| {
+                filter
+                    .skip_reason(entry.status(), &entry.voorkomen())
+                    .is_none()
+            })
+            .collect();
+        let nummeraanduidingen = Postcodes {
+            identificatie: numbers.iter().map(|e| e.identificatie).collect(),
+            postcodes: numbers
+                .iter()
+                .map(|e| Postcode::from_u32(e.postcode))
+                .collect(),
+            huisnummers: numbers
+                .iter()
+                .map(|e| Huisnummer {
+                    nummer: e.nummer,
+                    letter: if e.letter == 0 { None } else { Some(e.letter) },
+                    toevoeging: Toevoeging::from_bytes(e.toevoeging),
+                })
+                .collect(),
+            status: numbers.iter().map(|e| e.status()).collect(),
+            voorkomens: numbers.iter().map(|e| e.voorkomen()).collect(),
+            report: Report::default(),
+        };
+
+        (verblijfsobjecten, nummeraanduidingen)
+    }
+}
+
+/// The entries of the parsed Verblijfsobjecten, with their voorkomen
+fn verblijfsobject_entries(
+    verblijfsobjecten: &Verblijfsobjecten,
+) -> impl Iterator<Item = (VerblijfsobjectEntry, &Voorkomen)> + '_ {
+    let it = verblijfsobjecten
+        .identificatie
+        .iter()
+        .zip(&verblijfsobjecten.postcode_id)
+        .zip(&verblijfsobjecten.points)
+        .zip(&verblijfsobjecten.status)
+        .zip(&verblijfsobjecten.voorkomens);
+
+    it.map(
+        |((((identificatie, hoofdadres), point), status), voorkomen)| {
+            let entry = VerblijfsobjectEntry {
+                identificatie: *identificatie,
+                hoofdadres: *hoofdadres,
+                point: *point,
+                voorkomen: voorkomen.identificatie,
+                begin_geldigheid: voorkomen.begin_geldigheid.unwrap_or(0),
+                eind_geldigheid: voorkomen.eind_geldigheid.unwrap_or(0),
+                status: status_index(*status),
+                _padding: [0; 3],
+            };
+
+            (entry, voorkomen)
+        },
+    )
+}
+
+/// The entries of the parsed Nummeraanduidingen, with their voorkomen
+fn nummeraanduiding_entries(
+    nummeraanduidingen: &Postcodes,
+) -> impl Iterator<Item = (NummeraanduidingEntry, &Voorkomen)> + '_ {
+    let it = nummeraanduidingen
+        .identificatie
+        .iter()
+        .zip(&nummeraanduidingen.postcodes)
+        .zip(&nummeraanduidingen.huisnummers)
+        .zip(&nummeraanduidingen.status)
+        .zip(&nummeraanduidingen.voorkomens);
+
+    it.map(
+        |((((identificatie, postcode), huisnummer), status), voorkomen)| {
+            let entry = NummeraanduidingEntry {
+                identificatie: *identificatie,
+                postcode: postcode.as_u32(),
+                nummer: huisnummer.nummer,
+                toevoeging: huisnummer
+                    .toevoeging
+                    .map(Toevoeging::to_bytes)
+                    .unwrap_or_default(),
+                voorkomen: voorkomen.identificatie,
+                begin_geldigheid: voorkomen.begin_geldigheid.unwrap_or(0),
+                eind_geldigheid: voorkomen.eind_geldigheid.unwrap_or(0),
+                letter: huisnummer.letter.unwrap_or(0),
+                status: status_index(*status),
+                _padding: [0; 6],
+            };
+
+            (entry, voorkomen)
+        },
+    )
+}
+
+/// A voorkomen in the mutations
+#[derive(Debug, Clone, Copy)]
+struct Change<T> {
+    /// the identificatie of the object and of the voorkomen
+    key: (u64, u32),
+    /// of the states of one voorkomen in the mutations, a correction wins over an end, and an
+    /// end over no end: a voorkomen only ever gets those
+    order: (bool, bool),
+    /// `None` when the voorkomen is not kept
+    entry: Option<T>,
+}
+
+impl<T: Entry> Change<T> {
+    fn new(statuses: Statuses, date: Option<u32>, entry: T, voorkomen: &Voorkomen) -> Self {
+        let kept = statuses.contains(entry.status()) && !voorkomen.has_ended(date);
+
+        Self {
+            key: entry.key(),
+            order: (
+                voorkomen.eind_registratie,
+                voorkomen.eind_geldigheid.is_some(),
+            ),
+            entry: kept.then_some(entry),
+        }
+    }
+}
+
+/// Merge `changes` into `old` (which is sorted by key)
+fn upsert<T: Entry>(old: Vec<T>, mut changes: Vec<Change<T>>) -> Vec<T> {
+    // stable, so of equal states the last one in the mutations wins
+    changes.sort_by_key(|change| (change.key, change.order));
+
+    let mut latest: Vec<Change<T>> = Vec::with_capacity(changes.len());
+    for change in changes {
+        match latest.last_mut() {
+            Some(last) if last.key == change.key => *last = change,
+            _ => latest.push(change),
+        }
+    }
+
+    let mut result = Vec::with_capacity(old.len() + latest.len());
+    let mut old = old.into_iter().peekable();
+
+    for change in latest {
+        while let Some(current) = old.next_if(|current| current.key() < change.key) {
+            result.push(current);
+        }
+
+        // the stored state of this voorkomen is replaced
+        while old.next_if(|current| current.key() == change.key).is_some() {}
+
+        result.extend(change.entry);
+    }
+
+    result.extend(old);
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::join;
+    use crate::points::{PostcodeLayout, PostcodePoints};
+    use crate::source::ZipSource;
+    use crate::{parse_num, parse_vbo, query};
+
+    use std::io::Write;
+
+    fn voorkomen(identificatie: u32, begin: &str, eind: Option<&str>) -> String {
+        let eind = eind
+            .map(|eind| {
+                format!(
+                    "<Historie:eindGeldigheid>{}</Historie:eindGeldigheid>",
+                    eind
+                )
+            })
+            .unwrap_or_default();
+
+        format!(
+            "<Objecten:voorkomen><Historie:Voorkomen>
+<Historie:voorkomenidentificatie>{}</Historie:voorkomenidentificatie>
+<Historie:beginGeldigheid>{}</Historie:beginGeldigheid>{}
+</Historie:Voorkomen></Objecten:voorkomen>",
+            identificatie, begin, eind
+        )
+    }
+
+    fn verblijfsobject(
+        id: u64,
+        voorkomen: String,
+        status: Status,
+        hoofdadres: u64,
+        x: u32,
+    ) -> String {
+        format!(
+            r#"<Objecten:Verblijfsobject>
+<Objecten:heeftAlsHoofdadres><Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">{:016}</Objecten-ref:NummeraanduidingRef></Objecten:heeftAlsHoofdadres>
+{}
+<Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">{:016}</Objecten:identificatie>
+<Objecten:geometrie><Objecten:punt><gml:Point srsName="urn:ogc:def:crs:EPSG::28992" srsDimension="3"><gml:pos>{}.000 487000.000 0.0</gml:pos></gml:Point></Objecten:punt></Objecten:geometrie>
+<Objecten:status>{}</Objecten:status>
+</Objecten:Verblijfsobject>"#,
+            hoofdadres, voorkomen, id, x, status
+        )
+    }
+
+    fn nummeraanduiding(id: u64, postcode: &str, huisnummer: u32) -> String {
+        format!(
+            r#"<Objecten:Nummeraanduiding>
+<Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">{:016}</Objecten:identificatie>
+{}
+<Objecten:huisnummer>{}</Objecten:huisnummer>
+<Objecten:postcode>{}</Objecten:postcode>
+<Objecten:status>Naamgeving uitgegeven</Objecten:status>
+</Objecten:Nummeraanduiding>"#,
+            id,
+            voorkomen(1, "2020-01-01", None),
+            huisnummer,
+            postcode
+        )
+    }
+
+    fn zip_with(path: &Path, members: &[(&str, String)]) {
+        let file = std::fs::File::create(path).unwrap();
+        let mut writer = zip::ZipWriter::new(file);
+        let options = zip::write::FileOptions::default();
+
+        for (name, contents) in members {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+
+        writer.finish().unwrap();
+    }
+
+    fn parse(path: &Path) -> (Verblijfsobjecten, Postcodes) {
+        let source = ZipSource::File(path.to_path_buf());
+        let mode = crate::report::Mode::Strict;
+
+        let verblijfsobjecten = parse_vbo::parse(&source, mode).unwrap();
+        let nummeraanduidingen = parse_num::parse(&source, mode).unwrap();
+
+        (verblijfsobjecten, nummeraanduidingen)
+    }
+
+    fn parse_filtered(path: &Path, date: u32) -> (Verblijfsobjecten, Postcodes) {
+        let filter = Filter {
+            statuses: Statuses::DEFAULT,
+            date,
+        };
+
+        let (verblijfsobjecten, nummeraanduidingen) = parse(path);
+        (
+            verblijfsobjecten.filter(&filter),
+            nummeraanduidingen.filter(&filter),
+        )
+    }
+
+    /// The bytes of the index file for these objects
+    fn index_file(
+        parsed: (Verblijfsobjecten, Postcodes),
+        date: Option<u32>,
+        path: &Path,
+    ) -> Vec<u8> {
+        let (verblijfsobjecten, nummeraanduidingen) = parsed;
+
+        let mut report = Report::default();
+        let method = join::Method::HashMap;
+        let addresses = join::join(method, verblijfsobjecten, nummeraanduidingen, &mut report);
+
+        let items = addresses.iter().map(|adres| (adres.postcode, adres.point));
+        let postcode_points = PostcodePoints::<MillimeterPoint>::new(items);
+
+        let layout = PostcodeLayout::Sparse;
+        query::Index::create_file(path, date, &addresses, &postcode_points, layout).unwrap();
+
+        std::fs::read(path).unwrap()
+    }
+
+    impl Entry for (u64, u32) {
+        fn key(&self) -> (u64, u32) {
+            *self
+        }
+
+        fn status(&self) -> Status {
+            Status::InGebruik
+        }
+
+        fn voorkomen(&self) -> Voorkomen {
+            Voorkomen {
+                identificatie: self.1,
+                ..Voorkomen::default()
+            }
+        }
+    }
+
+    #[test]
+    fn upsert_replaces() {
+        let change = |key, corrected: bool, ended: bool| Change {
+            key,
+            order: (corrected, ended),
+            entry: (!corrected && !ended).then_some(key),
+        };
+
+        let old = vec![(1, 1), (3, 1), (3, 2), (5, 1), (7, 1)];
+        let changes = vec![
+            // the end of a voorkomen, and the next one
+            change((7, 1), false, true),
+            change((7, 2), false, false),
+            // a new object
+            change((4, 1), false, false),
+            // a correction of an older voorkomen
+            change((3, 1), true, false),
+            // a voorkomen that was corrected, after it got an end
+            change((5, 1), true, false),
+            change((5, 1), false, false),
+            // a voorkomen that only starts later
+            change((8, 1), false, false),
+        ];
+
+        let result = upsert(old, changes);
+        assert_eq!(vec![(1, 1), (3, 2), (4, 1), (7, 2), (8, 1)], result);
+    }
+
+    #[test]
+    fn apply_mutations() {
+        let dir = std::env::temp_dir().join("bagextract-mutations");
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let current = || voorkomen(1, "2020-01-01", None);
+        let ended = || voorkomen(1, "2020-01-01", Some("2021-10-09"));
+        let next = || voorkomen(2, "2021-10-09", None);
+        // a voorkomen that starts after the date of the extract, and the one before it
+        let ending = || voorkomen(1, "2020-01-01", Some("2021-10-10"));
+        let future = || voorkomen(2, "2021-10-10", None);
+
+        use Status::{Gevormd, InGebruik, Ingetrokken};
+
+        // 5 is demolished, 6 is taken into use and 7 moves on 2021-10-10. For 5 and 6 that is
+        // already known in the extract, for 7 it is in the mutations of 2021-10-09.
+        let old = [
+            verblijfsobject(1, current(), InGebruik, 11, 121_000),
+            verblijfsobject(2, current(), InGebruik, 12, 121_100),
+            verblijfsobject(4, current(), Gevormd, 12, 121_400),
+            verblijfsobject(5, ending(), InGebruik, 11, 121_500),
+            verblijfsobject(5, future(), Ingetrokken, 11, 121_500),
+            verblijfsobject(6, ending(), Gevormd, 12, 121_600),
+            verblijfsobject(6, future(), InGebruik, 12, 121_600),
+            verblijfsobject(7, current(), InGebruik, 11, 121_700),
+            nummeraanduiding(11, "1012BS", 1),
+            nummeraanduiding(12, "1012BS", 2),
+        ];
+
+        // 1 is demolished, 2 moves and gets a new hoofdadres in another postcode, 4 is taken
+        // into use. The full extract also contains the voorkomens that ended.
+        let new = [
+            nummeraanduiding(11, "1012BS", 1),
+            nummeraanduiding(12, "1012BS", 2),
+            nummeraanduiding(13, "1012BT", 5),
+            verblijfsobject(4, next(), InGebruik, 12, 121_400),
+            verblijfsobject(4, ended(), Gevormd, 12, 121_400),
+            verblijfsobject(2, ended(), InGebruik, 12, 121_100),
+            verblijfsobject(2, next(), InGebruik, 13, 121_200),
+            verblijfsobject(1, ended(), InGebruik, 11, 121_000),
+            verblijfsobject(1, next(), Ingetrokken, 11, 121_000),
+            verblijfsobject(5, ending(), InGebruik, 11, 121_500),
+            verblijfsobject(5, future(), Ingetrokken, 11, 121_500),
+            verblijfsobject(6, ending(), Gevormd, 12, 121_600),
+            verblijfsobject(6, future(), InGebruik, 12, 121_600),
+            verblijfsobject(7, ending(), InGebruik, 11, 121_700),
+            verblijfsobject(7, future(), InGebruik, 11, 121_750),
+        ];
+
+        // nothing changes on 2021-10-10 but a new Nummeraanduiding
+        let newer = [new.concat(), nummeraanduiding(14, "1012BT", 6)];
+
+        let wijziging = |was, wordt| {
+            format!(
+                "<ml:wijziging><ml:was>{}</ml:was><ml:wordt>{}</ml:wordt></ml:wijziging>",
+                was, wordt
+            )
+        };
+        let toevoeging = |wordt| {
+            format!(
+                "<ml:toevoeging><ml:wordt>{}</ml:wordt></ml:toevoeging>",
+                wordt
+            )
+        };
+
+        let mutations = [
+            wijziging(
+                verblijfsobject(2, current(), InGebruik, 12, 121_100),
+                verblijfsobject(2, ended(), InGebruik, 12, 121_100),
+            ),
+            toevoeging(verblijfsobject(2, next(), InGebruik, 13, 121_200)),
+            toevoeging(verblijfsobject(1, next(), Ingetrokken, 11, 121_000)),
+            wijziging(
+                verblijfsobject(1, current(), InGebruik, 11, 121_000),
+                verblijfsobject(1, ended(), InGebruik, 11, 121_000),
+            ),
+            toevoeging(verblijfsobject(4, next(), InGebruik, 12, 121_400)),
+            toevoeging(nummeraanduiding(13, "1012BT", 5)),
+            toevoeging(verblijfsobject(7, future(), InGebruik, 11, 121_750)),
+            wijziging(
+                verblijfsobject(7, current(), InGebruik, 11, 121_700),
+                verblijfsobject(7, ending(), InGebruik, 11, 121_700),
+            ),
+        ];
+        let next_mutations = [toevoeging(nummeraanduiding(14, "1012BT", 6))];
+
+        let old_zip = dir.join("old.zip");
+        let new_zip = dir.join("new.zip");
+        let newer_zip = dir.join("newer.zip");
+        let mutations_zip = dir.join("9999MUT08102021-09102021.zip");
+        let next_mutations_zip = dir.join("9999MUT09102021-10102021.zip");
+
+        zip_with(&old_zip, &[("a.xml", old.concat())]);
+        zip_with(&new_zip, &[("a.xml", new.concat())]);
+        zip_with(&newer_zip, &[("a.xml", newer.concat())]);
+        zip_with(&mutations_zip, &[("mutaties.xml", mutations.concat())]);
+        zip_with(
+            &next_mutations_zip,
+            &[("mutaties.xml", next_mutations.concat())],
+        );
+
+        let statuses = Statuses::DEFAULT;
+        let (verblijfsobjecten, nummeraanduidingen) = parse(&old_zip);
+        let mut state = State::new(
+            Some(20211008),
+            statuses,
+            &verblijfsobjecten,
+            &nummeraanduidingen,
+        );
+
+        let (verblijfsobjecten, _) = state.to_parsed();
+        assert_eq!(vec![1, 2, 5, 7], verblijfsobjecten.identificatie);
+
+        let source = ZipSource::mutations(&mutations_zip).unwrap();
+        assert_eq!(ZipSource::File(mutations_zip.clone()), source);
+
+        let (verblijfsobjecten, nummeraanduidingen) = parse(&mutations_zip);
+        assert_eq!(vec![2, 2, 1, 1, 4, 7, 7], verblijfsobjecten.identificatie);
+        let period = source.mutation_period().unwrap();
+        state
+            .apply(period, &verblijfsobjecten, &nummeraanduidingen)
+            .unwrap();
+
+        // the same mutations again, or the mutations of a later day, do not fit the state
+        let mut applied = state.clone();
+        let again = applied.apply(period, &verblijfsobjecten, &nummeraanduidingen);
+        assert!(again.is_err());
+        let later = (20211010, 20211011);
+        assert!(applied
+            .apply(later, &verblijfsobjecten, &nummeraanduidingen)
+            .is_err());
+        assert_eq!(state, applied);
+
+        let state_path = dir.join(STATE_FILE);
+        state.write(&state_path).unwrap();
+        let mut state = State::read(&state_path).unwrap();
+
+        // the state is the same as that of the full extract, on both days
+        let check = |state: &State, zip: &Path, date: u32, expected: Vec<u64>| {
+            let (verblijfsobjecten, nummeraanduidingen) = parse(zip);
+            let fresh = State::new(
+                Some(date),
+                statuses,
+                &verblijfsobjecten,
+                &nummeraanduidingen,
+            );
+            assert_eq!(&fresh, state);
+
+            let (verblijfsobjecten, nummeraanduidingen) = parse_filtered(zip, date);
+            let mut ids = verblijfsobjecten.identificatie.clone();
+            ids.sort_unstable();
+            assert_eq!(expected, ids);
+
+            let date = state.extract_date;
+            let updated = index_file(state.to_parsed(), date, &dir.join("updated.bin"));
+            let parsed = (verblijfsobjecten, nummeraanduidingen);
+            let generated = index_file(parsed, date, &dir.join("generated.bin"));
+            assert!(updated == generated);
+        };
+
+        check(&state, &new_zip, 20211009, vec![2, 4, 5, 7]);
+
+        let (verblijfsobjecten, nummeraanduidingen) = parse(&next_mutations_zip);
+        let source = ZipSource::File(next_mutations_zip);
+        state
+            .apply(
+                source.mutation_period().unwrap(),
+                &verblijfsobjecten,
+                &nummeraanduidingen,
+            )
+            .unwrap();
+
+        check(&state, &newer_zip, 20211010, vec![2, 4, 6, 7]);
+    }
+}
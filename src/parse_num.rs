@@ -1,49 +1,105 @@
 // Parse Nummeraanduiding zip file
 
-use std::fs::File;
 use std::io::BufReader;
-use std::path::Path;
 
+use zip::ZipArchive;
+
+use crate::adres::{Huisnummer, Toevoeging};
+use crate::error::{parse_text, Error};
 use crate::postcode::Postcode;
+use crate::report::{Mode, Reason, Report};
+use crate::source::{Window, ZipSource};
+use crate::status::{parse_date, Filter, Status, Voorkomen};
 
 #[derive(Debug, Default)]
 pub struct Postcodes {
     pub identificatie: Vec<u64>,
     pub postcodes: Vec<Postcode>,
+    pub huisnummers: Vec<Huisnummer>,
+    /// the status of every Nummeraanduiding
+    pub status: Vec<Status>,
+    /// the voorkomen of every Nummeraanduiding
+    pub voorkomens: Vec<Voorkomen>,
+    /// the Nummeraanduidingen that were skipped
+    pub report: Report,
 }
 
 impl Postcodes {
-    fn push(&mut self, identificatie: u64, postcode: Postcode) {
+    fn push(
+        &mut self,
+        identificatie: u64,
+        postcode: Postcode,
+        huisnummer: Huisnummer,
+        status: Status,
+        voorkomen: Voorkomen,
+    ) {
         self.identificatie.push(identificatie);
         self.postcodes.push(postcode);
+        self.huisnummers.push(huisnummer);
+        self.status.push(status);
+        self.voorkomens.push(voorkomen);
     }
 
     fn merge(mut self, other: Self) -> Self {
         self.identificatie.extend(other.identificatie);
         self.postcodes.extend(other.postcodes);
+        self.huisnummers.extend(other.huisnummers);
+        self.status.extend(other.status);
+        self.voorkomens.extend(other.voorkomens);
+        self.report = self.report.merge(other.report);
 
         self
     }
+
+    /// Keep the Nummeraanduidingen that pass `filter`, and add the others to the report
+    pub fn filter(self, filter: &Filter) -> Self {
+        let mut result = Self {
+            report: self.report,
+            ..Self::default()
+        };
+
+        for i in 0..self.identificatie.len() {
+            let identificatie = self.identificatie[i];
+            let (status, voorkomen) = (self.status[i], self.voorkomens[i]);
+
+            match filter.skip_reason(status, &voorkomen) {
+                Some(reason) => result.report.skip(reason, || identificatie.to_string()),
+                None => {
+                    let (postcode, huisnummer) = (self.postcodes[i], self.huisnummers[i]);
+                    result.push(identificatie, postcode, huisnummer, status, voorkomen);
+                }
+            }
+        }
+
+        result
+    }
 }
 
-pub fn parse(path: &Path) -> std::io::Result<Postcodes> {
-    let file = std::fs::File::open(path)?;
-    let archive = zip::ZipArchive::new(file).unwrap();
+pub fn parse(source: &ZipSource, mode: Mode) -> Result<Postcodes, Error> {
+    let archive = source.open()?;
 
     let range = 0..archive.len();
     // let range = 0..10;
 
-    let result = parse_step(path, range.start, range.end)?;
+    let result = parse_step(source, mode, range.start, range.end)?;
 
     Ok(result)
 }
 
-fn parse_ith_xml_file(archive: &mut zip::ZipArchive<File>, i: usize) -> Option<Postcodes> {
-    let file = archive.by_index(i).unwrap();
+fn parse_ith_xml_file(
+    source: &ZipSource,
+    mode: Mode,
+    archive: &mut ZipArchive<Window>,
+    i: usize,
+) -> Result<Option<Postcodes>, Error> {
+    let file = archive.by_index(i).map_err(|error| Error::Zip {
+        archive: source.to_string(),
+        error,
+    })?;
 
     if file.name().ends_with('/') {
         println!("Entry {} is a directory with name \"{}\"", i, file.name());
-        None
+        Ok(None)
     } else {
         println!(
             "Entry {} is a file with name \"{}\" ({} bytes)",
@@ -52,49 +108,67 @@ fn parse_ith_xml_file(archive: &mut zip::ZipArchive<File>, i: usize) -> Option<P
             file.size()
         );
 
+        let entry = format!("{}/{}", source, file.name());
         let reader = BufReader::new(file);
         let mut result = Postcodes::default();
-        parse_manual_step(reader, &mut result).unwrap();
+        parse_manual_step(reader, &entry, mode, &mut result)?;
 
-        Some(result)
+        Ok(Some(result))
     }
 }
 
-fn parse_step(path: &Path, start: usize, end: usize) -> std::io::Result<Postcodes> {
+fn parse_step(
+    source: &ZipSource,
+    mode: Mode,
+    start: usize,
+    end: usize,
+) -> Result<Postcodes, Error> {
     use rayon::prelude::*;
 
-    let init = || {
-        let file = std::fs::File::open(path).unwrap();
-        zip::ZipArchive::new(file).unwrap()
-    };
+    // every thread opens the archive for itself. `parse` has already opened it once, so this
+    // only fails if the file changed underneath us
+    let init = || source.open();
 
-    let result = (start..end)
+    (start..end)
         .into_par_iter()
-        .map_init(init, parse_ith_xml_file)
-        .filter_map(|x| x)
-        .reduce(Postcodes::default, Postcodes::merge);
-
-    Ok(result)
+        .map_init(init, |archive, i| match archive {
+            Ok(archive) => parse_ith_xml_file(source, mode, archive, i),
+            Err(error) => Err(Error::reopen(error)),
+        })
+        .filter_map(|x| x.transpose())
+        .try_reduce(Postcodes::default, |a, b| Ok(a.merge(b)))
 }
 
 #[derive(Debug)]
 pub struct Nummeraanduiding {
     identificatie: u64,
     postcode: Option<Postcode>,
+    huisnummer: Huisnummer,
+    status: Option<Status>,
+    voorkomen: Voorkomen,
 }
 
-pub fn parse_manual_str(input: &str) -> Option<Postcodes> {
+pub fn parse_manual_str(input: &str) -> Result<Postcodes, Error> {
     let mut result = Postcodes {
         identificatie: Vec::with_capacity(10_000),
         postcodes: Vec::with_capacity(10_000),
+        huisnummers: Vec::with_capacity(10_000),
+        status: Vec::with_capacity(10_000),
+        voorkomens: Vec::with_capacity(10_000),
+        report: Report::default(),
     };
 
-    parse_manual_step(input.as_bytes(), &mut result)?;
+    parse_manual_step(input.as_bytes(), "input", Mode::Strict, &mut result)?;
 
-    Some(result)
+    Ok(result)
 }
 
-fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Postcodes) -> Option<()> {
+fn parse_manual_step<B: std::io::BufRead>(
+    input: B,
+    entry: &str,
+    mode: Mode,
+    result: &mut Postcodes,
+) -> Result<(), Error> {
     use quick_xml::events::Event;
     use quick_xml::Reader;
 
@@ -105,14 +179,46 @@ fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Postcodes) -> O
         match reader.read_event(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 if let b"Objecten:Nummeraanduiding" = e.name() {
-                    let aanduiding = parse_manual_help(&mut reader, &mut buf)?;
-                    if let Some(postcode) = aanduiding.postcode {
-                        // println!("identificatie {:?}", aanduiding.identificatie);
-                        result.push(aanduiding.identificatie, postcode);
+                    let report = &mut result.report;
+                    match parse_manual_help(&mut reader, &mut buf, entry, report) {
+                        Ok(Some(Nummeraanduiding {
+                            identificatie,
+                            postcode: Some(postcode),
+                            huisnummer,
+                            status: Some(status),
+                            voorkomen,
+                        })) => result.push(identificatie, postcode, huisnummer, status, voorkomen),
+                        Ok(Some(Nummeraanduiding {
+                            identificatie,
+                            postcode,
+                            ..
+                        })) => {
+                            let reason = match postcode {
+                                None => Reason::MissingPostcode,
+                                Some(_) => Reason::MissingStatus,
+                            };
+                            report.skip(reason, || format!("{} in {}", identificatie, entry));
+                        }
+                        Ok(None) => (),
+                        Err(error) if mode == Mode::Lenient => {
+                            report.skip_invalid(error)?;
+
+                            // skip the rest of the malformed Nummeraanduiding
+                            reader
+                                .read_to_end(b"Objecten:Nummeraanduiding", &mut buf)
+                                .map_err(|e| Error::xml(entry, reader.buffer_position(), e))?;
+                        }
+                        Err(error) => return Err(error),
                     }
+                } else if e.local_name() == b"was" {
+                    // in a mutation file, only the state after a `wijziging` (`wordt`) counts
+                    let name = e.name().to_vec();
+                    reader
+                        .read_to_end(name, &mut buf)
+                        .map_err(|e| Error::xml(entry, reader.buffer_position(), e))?;
                 }
             }
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Err(e) => return Err(Error::xml(entry, reader.buffer_position(), e)),
             Ok(Event::Eof) => break,
             _ => (),
         }
@@ -120,72 +226,149 @@ fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Postcodes) -> O
         buf.clear();
     }
 
-    Some(())
+    Ok(())
 }
 
+/// Parse a single Nummeraanduiding. Returns `None` (and adds it to the report) if it has no
+/// identificatie.
 fn parse_manual_help<B: std::io::BufRead>(
     reader: &mut quick_xml::Reader<B>,
     buf: &mut Vec<u8>,
-) -> Option<Nummeraanduiding> {
+    entry: &str,
+    report: &mut Report,
+) -> Result<Option<Nummeraanduiding>, Error> {
     use quick_xml::events::Event;
 
     enum State {
         None,
         Identificatie,
         Postcode,
+        Huisnummer,
+        Huisletter,
+        Huisnummertoevoeging,
+        Status,
+        Voorkomen,
+        BeginGeldigheid,
+        EindGeldigheid,
     }
 
     let mut state = State::None;
 
     let mut identificatie = None;
     let mut postcode = None;
+    let mut huisnummer = Huisnummer::default();
+    let mut status = None;
+    let mut voorkomen = Voorkomen::default();
 
     loop {
         match reader.read_event(buf) {
             Ok(Event::Start(ref e)) => match e.name() {
                 b"Objecten:identificatie" => state = State::Identificatie,
                 b"Objecten:postcode" => state = State::Postcode,
+                b"Objecten:huisnummer" => state = State::Huisnummer,
+                b"Objecten:huisletter" => state = State::Huisletter,
+                b"Objecten:huisnummertoevoeging" => state = State::Huisnummertoevoeging,
+                b"Objecten:status" => state = State::Status,
+                b"Historie:voorkomenidentificatie" => state = State::Voorkomen,
+                b"Historie:beginGeldigheid" => state = State::BeginGeldigheid,
+                b"Historie:eindGeldigheid" => state = State::EindGeldigheid,
+                b"Historie:eindRegistratie" => voorkomen.eind_registratie = true,
                 _ => (),
             },
             Ok(Event::End(ref e)) => {
                 if let b"Objecten:Nummeraanduiding" = e.name() {
-                    match identificatie {
-                        Some(identificatie) => {
-                            return Some(Nummeraanduiding {
-                                identificatie,
-                                postcode,
-                            })
-                        }
-                        None => return None,
+                    let aanduiding = identificatie.map(|identificatie| Nummeraanduiding {
+                        identificatie,
+                        postcode,
+                        huisnummer,
+                        status,
+                        voorkomen,
+                    });
+
+                    if aanduiding.is_none() {
+                        let position = reader.buffer_position();
+                        let reason = Reason::MissingIdentificatie;
+                        report.skip(reason, || format!("{} at byte {}", entry, position));
                     }
+
+                    return Ok(aanduiding);
                 }
             }
-            Ok(Event::Text(e)) => match state {
-                State::None => (),
-                State::Identificatie => {
-                    let string = unsafe { std::str::from_utf8_unchecked(&e) };
-                    identificatie = Some(string.parse().unwrap());
-                    state = State::None;
-                }
-                State::Postcode => {
-                    let string = unsafe { std::str::from_utf8_unchecked(&e) };
-                    postcode = Some(Postcode::try_from(string).unwrap());
-                    state = State::None;
+            Ok(Event::Text(e)) => {
+                let position = reader.buffer_position();
+
+                match state {
+                    State::None => (),
+                    State::Identificatie => {
+                        let value = parse_text(entry, position, "identificatie", &e, str::parse)?;
+                        identificatie = Some(value);
+                        state = State::None;
+                    }
+                    State::Postcode => {
+                        // postcodes below `Postcode::MIN` (e.g. 0999ZZ) do not exist
+                        let valid = |text: &str| match Postcode::try_from(text) {
+                            Ok(postcode) if postcode.as_dense_index().is_some() => Ok(postcode),
+                            _ => Err(()),
+                        };
+                        let value = parse_text(entry, position, "postcode", &e, valid)?;
+                        postcode = Some(value);
+                        state = State::None;
+                    }
+                    State::Huisnummer => {
+                        huisnummer.nummer =
+                            parse_text(entry, position, "huisnummer", &e, str::parse)?;
+                        state = State::None;
+                    }
+                    State::Huisletter => {
+                        huisnummer.letter = match e.as_ref() {
+                            [letter] if letter.is_ascii_alphabetic() => Some(*letter),
+                            _ => {
+                                return Err(Error::invalid_value(entry, position, "huisletter", &e))
+                            }
+                        };
+                        state = State::None;
+                    }
+                    State::Huisnummertoevoeging => {
+                        let field = "huisnummertoevoeging";
+                        let value = parse_text(entry, position, field, &e, Toevoeging::try_from)?;
+                        huisnummer.toevoeging = Some(value);
+                        state = State::None;
+                    }
+                    State::Status => {
+                        status = Some(parse_text(entry, position, "status", &e, str::parse)?);
+                        state = State::None;
+                    }
+                    State::Voorkomen => {
+                        let field = "voorkomenidentificatie";
+                        voorkomen.identificatie =
+                            parse_text(entry, position, field, &e, str::parse)?;
+                        state = State::None;
+                    }
+                    State::BeginGeldigheid => {
+                        let field = "beginGeldigheid";
+                        let date =
+                            parse_text(entry, position, field, &e, |s| parse_date(s).ok_or(()))?;
+                        voorkomen.begin_geldigheid = Some(date);
+                        state = State::None;
+                    }
+                    State::EindGeldigheid => {
+                        let field = "eindGeldigheid";
+                        let date =
+                            parse_text(entry, position, field, &e, |s| parse_date(s).ok_or(()))?;
+                        voorkomen.eind_geldigheid = Some(date);
+                        state = State::None;
+                    }
                 }
-            },
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => return None,
+            }
+            Err(e) => return Err(Error::xml(entry, reader.buffer_position(), e)),
+            Ok(Event::Eof) => {
+                let error = quick_xml::Error::UnexpectedEof("Objecten:Nummeraanduiding".into());
+                return Err(Error::xml(entry, reader.buffer_position(), error));
+            }
             _ => (),
         }
 
         buf.clear();
-
-        if let (Some(identificatie), Some(postcode)) = (identificatie, postcode) {
-            return Some(Nummeraanduiding {
-                identificatie,
-                postcode: Some(postcode),
-            });
-        }
     }
 }
 
@@ -232,11 +415,99 @@ mod test {
 "#;
 
         let mut reader = quick_xml::Reader::from_str(input);
-        let object: Nummeraanduiding = parse_manual_help(&mut reader, &mut Vec::new()).unwrap();
+        let object: Nummeraanduiding = parse_manual_help(
+            &mut reader,
+            &mut Vec::new(),
+            "input",
+            &mut Report::default(),
+        )
+        .unwrap()
+        .unwrap();
 
         dbg!(&object);
     }
 
+    #[test]
+    fn parse_huisnummer() {
+        let input = r#"
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123456</Objecten:identificatie>
+                <Objecten:huisnummer>12</Objecten:huisnummer>
+                <Objecten:huisletter>A</Objecten:huisletter>
+                <Objecten:huisnummertoevoeging>bis</Objecten:huisnummertoevoeging>
+                <Objecten:postcode>1012BS</Objecten:postcode>
+                <Objecten:status>Naamgeving uitgegeven</Objecten:status>
+                <Objecten:typeAdresseerbaarObject>Verblijfsobject</Objecten:typeAdresseerbaarObject>
+            </Objecten:Nummeraanduiding>
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123457</Objecten:identificatie>
+                <Objecten:voorkomen>
+                    <Historie:Voorkomen>
+                        <Historie:voorkomenidentificatie>2</Historie:voorkomenidentificatie>
+                        <Historie:beginGeldigheid>2018-03-26</Historie:beginGeldigheid>
+                        <Historie:eindGeldigheid>2021-10-08</Historie:eindGeldigheid>
+                        <Historie:tijdstipRegistratie>2018-03-26T11:32:29.000</Historie:tijdstipRegistratie>
+                    </Historie:Voorkomen>
+                </Objecten:voorkomen>
+                <Objecten:huisnummer>14</Objecten:huisnummer>
+                <Objecten:postcode>1012BS</Objecten:postcode>
+                <Objecten:status>Naamgeving ingetrokken</Objecten:status>
+            </Objecten:Nummeraanduiding>
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123458</Objecten:identificatie>
+                <Objecten:huisnummer>16</Objecten:huisnummer>
+                <Objecten:postcode>1012BS</Objecten:postcode>
+            </Objecten:Nummeraanduiding>
+        "#;
+
+        let object: Postcodes = parse_manual_str(input).unwrap();
+
+        assert_eq!(vec![363200000123456, 363200000123457], object.identificatie);
+        assert_eq!(
+            vec![Status::NaamgevingUitgegeven, Status::NaamgevingIngetrokken],
+            object.status
+        );
+        assert_eq!(2, object.voorkomens[1].identificatie);
+        assert_eq!(Some(20211008), object.voorkomens[1].eind_geldigheid);
+        assert_eq!(1, object.report.get(Reason::MissingStatus).unwrap().count);
+        assert_eq!(
+            vec!["12A-bis".to_string(), "14".to_string()],
+            object
+                .huisnummers
+                .iter()
+                .map(|h| h.to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn postcode_below_1000() {
+        let input = r#"
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123456</Objecten:identificatie>
+                <Objecten:huisnummer>12</Objecten:huisnummer>
+                <Objecten:postcode>0999ZZ</Objecten:postcode>
+                <Objecten:status>Naamgeving uitgegeven</Objecten:status>
+            </Objecten:Nummeraanduiding>
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123457</Objecten:identificatie>
+                <Objecten:huisnummer>14</Objecten:huisnummer>
+                <Objecten:postcode>1000AA</Objecten:postcode>
+                <Objecten:status>Naamgeving uitgegeven</Objecten:status>
+            </Objecten:Nummeraanduiding>
+        "#;
+
+        assert!(parse_manual_str(input).is_err());
+
+        let mut result = Postcodes::default();
+        parse_manual_step(input.as_bytes(), "input", Mode::Lenient, &mut result).unwrap();
+
+        assert_eq!(vec![363200000123457], result.identificatie);
+        assert_eq!(vec![Postcode::MIN], result.postcodes);
+        let invalid = result.report.get(Reason::InvalidValue("postcode")).unwrap();
+        assert_eq!(1, invalid.count);
+    }
+
     #[test]
     fn parse_nummeraanduiding_many_manual() {
         const INPUT: &str = include_str!("/home/folkertdev/Downloads/inspire/num_01.xml");
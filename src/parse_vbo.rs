@@ -1,50 +1,104 @@
 // Parse Verblijfsobject zip file
 use zip::ZipArchive;
 
-use std::fs::File;
 use std::io::BufReader;
-use std::path::Path;
 
-use crate::point::Point;
+use crate::error::{parse_text, Error};
+use crate::point::MillimeterPoint;
+use crate::report::{Mode, Reason, Report};
+use crate::source::{Window, ZipSource};
+use crate::status::{parse_date, Filter, Status, Voorkomen};
 
 #[derive(Debug, Default)]
 pub struct Verblijfsobjecten {
-    pub points: Vec<Point>,
+    /// identificatie of every Verblijfsobject
+    pub identificatie: Vec<u64>,
+    /// the geopunt of every Verblijfsobject, exactly as it is in the extract
+    pub points: Vec<MillimeterPoint>,
     /// postcode id for each geopunt
     pub postcode_id: Vec<u64>,
+    /// the status of every Verblijfsobject
+    pub status: Vec<Status>,
+    /// the voorkomen of every Verblijfsobject
+    pub voorkomens: Vec<Voorkomen>,
+    /// the Verblijfsobjecten that were skipped
+    pub report: Report,
 }
 
 impl Verblijfsobjecten {
-    fn push(&mut self, identificatie: u64, point: Point) {
-        self.postcode_id.push(identificatie);
-        self.points.push(point);
+    fn push(&mut self, object: &Verblijfsobject) {
+        let geopunt = object.geopunt;
+
+        self.identificatie.push(object.identificatie);
+        self.postcode_id.push(object.hoofdadres);
+        self.points
+            .push(MillimeterPoint::from_meters(geopunt.x, geopunt.y));
+        self.status.push(object.status);
+        self.voorkomens.push(object.voorkomen);
     }
 
     fn merge(mut self, other: Self) -> Self {
+        self.identificatie.extend(other.identificatie);
         self.postcode_id.extend(other.postcode_id);
         self.points.extend(other.points);
+        self.status.extend(other.status);
+        self.voorkomens.extend(other.voorkomens);
+        self.report = self.report.merge(other.report);
 
         self
     }
+
+    /// Keep the Verblijfsobjecten that pass `filter`, and add the others to the report
+    pub fn filter(self, filter: &Filter) -> Self {
+        let mut result = Self {
+            report: self.report,
+            ..Self::default()
+        };
+
+        for i in 0..self.identificatie.len() {
+            let identificatie = self.identificatie[i];
+            let (status, voorkomen) = (self.status[i], self.voorkomens[i]);
+
+            match filter.skip_reason(status, &voorkomen) {
+                Some(reason) => result.report.skip(reason, || identificatie.to_string()),
+                None => {
+                    result.identificatie.push(identificatie);
+                    result.postcode_id.push(self.postcode_id[i]);
+                    result.points.push(self.points[i]);
+                    result.status.push(status);
+                    result.voorkomens.push(voorkomen);
+                }
+            }
+        }
+
+        result
+    }
 }
 
-pub fn parse(path: &Path) -> std::io::Result<Verblijfsobjecten> {
-    let file = std::fs::File::open(path)?;
-    let archive = zip::ZipArchive::new(file).unwrap();
+pub fn parse(source: &ZipSource, mode: Mode) -> Result<Verblijfsobjecten, Error> {
+    let archive = source.open()?;
 
     let range = 0..archive.len();
 
-    let result = parse_step(path, range.start, range.end)?;
+    let result = parse_step(source, mode, range.start, range.end)?;
 
     Ok(result)
 }
 
-fn parse_ith_xml_file(archive: &mut ZipArchive<File>, i: usize) -> Option<Verblijfsobjecten> {
-    let file = archive.by_index(i).unwrap();
+fn parse_ith_xml_file(
+    source: &ZipSource,
+    mode: Mode,
+    archive: &mut ZipArchive<Window>,
+    i: usize,
+) -> Result<Option<Verblijfsobjecten>, Error> {
+    let file = archive.by_index(i).map_err(|error| Error::Zip {
+        archive: source.to_string(),
+        error,
+    })?;
 
     if (file.name()).ends_with('/') {
         println!("Entry {} is a directory with name \"{}\"", i, file.name());
-        None
+        Ok(None)
     } else {
         println!(
             "Entry {} is a file with name \"{}\" ({} bytes)",
@@ -53,43 +107,58 @@ fn parse_ith_xml_file(archive: &mut ZipArchive<File>, i: usize) -> Option<Verbli
             file.size()
         );
 
+        let entry = format!("{}/{}", source, file.name());
         let reader = BufReader::new(file);
         let mut result = Verblijfsobjecten::default();
-        parse_manual_step(reader, &mut result).unwrap();
+        parse_manual_step(reader, &entry, mode, &mut result)?;
 
-        Some(result)
+        Ok(Some(result))
     }
 }
 
-fn parse_step(path: &Path, start: usize, end: usize) -> std::io::Result<Verblijfsobjecten> {
+fn parse_step(
+    source: &ZipSource,
+    mode: Mode,
+    start: usize,
+    end: usize,
+) -> Result<Verblijfsobjecten, Error> {
     use rayon::prelude::*;
 
-    let init = || {
-        let file = std::fs::File::open(path).unwrap();
-        zip::ZipArchive::new(file).unwrap()
-    };
+    // every thread opens the archive for itself. `parse` has already opened it once, so this
+    // only fails if the file changed underneath us
+    let init = || source.open();
 
-    let result = (start..end)
+    (start..end)
         .into_par_iter()
-        .map_init(init, parse_ith_xml_file)
-        .filter_map(|x| x)
-        .reduce(Verblijfsobjecten::default, Verblijfsobjecten::merge);
-
-    Ok(result)
+        .map_init(init, |archive, i| match archive {
+            Ok(archive) => parse_ith_xml_file(source, mode, archive, i),
+            Err(error) => Err(Error::reopen(error)),
+        })
+        .filter_map(|x| x.transpose())
+        .try_reduce(Verblijfsobjecten::default, |a, b| Ok(a.merge(b)))
 }
 
-pub fn parse_manual_str(input: &str) -> Option<Verblijfsobjecten> {
+pub fn parse_manual_str(input: &str) -> Result<Verblijfsobjecten, Error> {
     let mut result = Verblijfsobjecten {
+        identificatie: Vec::with_capacity(10_000),
         points: Vec::with_capacity(10_000),
         postcode_id: Vec::with_capacity(10_000),
+        status: Vec::with_capacity(10_000),
+        voorkomens: Vec::with_capacity(10_000),
+        report: Report::default(),
     };
 
-    parse_manual_step(input.as_bytes(), &mut result)?;
+    parse_manual_step(input.as_bytes(), "input", Mode::Strict, &mut result)?;
 
-    Some(result)
+    Ok(result)
 }
 
-fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Verblijfsobjecten) -> Option<()> {
+fn parse_manual_step<B: std::io::BufRead>(
+    input: B,
+    entry: &str,
+    mode: Mode,
+    result: &mut Verblijfsobjecten,
+) -> Result<(), Error> {
     use quick_xml::events::Event;
     use quick_xml::Reader;
 
@@ -100,14 +169,29 @@ fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Verblijfsobject
         match reader.read_event(&mut buf) {
             Ok(Event::Start(ref e)) => {
                 if let b"Objecten:Verblijfsobject" = e.name() {
-                    let object = parse_manual_help(&mut reader, &mut buf)?;
-                    let geopunt = object.geopunt;
-                    let (x, y) = (geopunt.x, geopunt.y);
-                    let point = Point::new(x as f32, y as f32);
-                    result.push(object.identificatie, point);
+                    let report = &mut result.report;
+                    match parse_manual_help(&mut reader, &mut buf, entry, report) {
+                        Ok(Some(object)) => result.push(&object),
+                        Ok(None) => (),
+                        Err(error) if mode == Mode::Lenient => {
+                            report.skip_invalid(error)?;
+
+                            // skip the rest of the malformed Verblijfsobject
+                            reader
+                                .read_to_end(b"Objecten:Verblijfsobject", &mut buf)
+                                .map_err(|e| Error::xml(entry, reader.buffer_position(), e))?;
+                        }
+                        Err(error) => return Err(error),
+                    }
+                } else if e.local_name() == b"was" {
+                    // in a mutation file, only the state after a `wijziging` (`wordt`) counts
+                    let name = e.name().to_vec();
+                    reader
+                        .read_to_end(name, &mut buf)
+                        .map_err(|e| Error::xml(entry, reader.buffer_position(), e))?;
                 }
             }
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
+            Err(e) => return Err(Error::xml(entry, reader.buffer_position(), e)),
             Ok(Event::Eof) => break,
             _ => (),
         }
@@ -115,97 +199,160 @@ fn parse_manual_step<B: std::io::BufRead>(input: B, result: &mut Verblijfsobject
         buf.clear();
     }
 
-    Some(())
+    Ok(())
 }
 
 #[derive(Debug)]
 struct Verblijfsobject {
     identificatie: u64,
+    /// identificatie of the Nummeraanduiding
+    hoofdadres: u64,
     geopunt: Geopunt,
+    status: Status,
+    voorkomen: Voorkomen,
 }
 
+/// Parse a single Verblijfsobject. Returns `None` (and adds it to the report) if it has no
+/// identificatie, no hoofdadres, no geometry or no status.
 fn parse_manual_help<B: std::io::BufRead>(
     reader: &mut quick_xml::Reader<B>,
     buf: &mut Vec<u8>,
-) -> Option<Verblijfsobject> {
+    entry: &str,
+    report: &mut Report,
+) -> Result<Option<Verblijfsobject>, Error> {
     use quick_xml::events::Event;
     use std::str::FromStr;
 
     enum State {
         None,
-        Hoofdadres,
         Identificatie,
+        Hoofdadres,
+        Nummeraanduiding,
         Point,
         Polygon,
+        Status,
+        Voorkomen,
+        BeginGeldigheid,
+        EindGeldigheid,
     }
 
     let mut state = State::None;
 
     let mut identificatie = None;
+    let mut hoofdadres = None;
     let mut geopunt = None;
+    let mut status = None;
+    let mut voorkomen = Voorkomen::default();
 
     loop {
         match reader.read_event(buf) {
             Ok(Event::Start(ref e)) => match e.name() {
+                b"Objecten:identificatie" => state = State::Identificatie,
                 b"Objecten:heeftAlsHoofdadres" => state = State::Hoofdadres,
                 b"Objecten-ref:NummeraanduidingRef" => {
                     if let State::Hoofdadres = state {
-                        state = State::Identificatie
+                        state = State::Nummeraanduiding
                     }
                 }
                 b"gml:pos" => state = State::Point,
                 b"gml:posList" => state = State::Polygon,
+                b"Objecten:status" => state = State::Status,
+                b"Historie:voorkomenidentificatie" => state = State::Voorkomen,
+                b"Historie:beginGeldigheid" => state = State::BeginGeldigheid,
+                b"Historie:eindGeldigheid" => state = State::EindGeldigheid,
+                b"Historie:eindRegistratie" => voorkomen.eind_registratie = true,
                 _ => (),
             },
             Ok(Event::End(ref e)) => {
                 if let b"Objecten:Verblijfsobject" = e.name() {
-                    match (identificatie, geopunt) {
-                        (Some(identificatie), Some(geopunt)) => {
-                            return Some(Verblijfsobject {
+                    let reason = match (identificatie, hoofdadres, geopunt, status) {
+                        (Some(identificatie), Some(hoofdadres), Some(geopunt), Some(status)) => {
+                            return Ok(Some(Verblijfsobject {
                                 identificatie,
+                                hoofdadres,
                                 geopunt,
-                            })
+                                status,
+                                voorkomen,
+                            }))
                         }
-                        _ => return None,
-                    }
+                        (None, _, _, _) => Reason::MissingIdentificatie,
+                        (Some(_), None, _, _) => Reason::MissingHoofdadres,
+                        (Some(_), Some(_), None, _) => Reason::MissingGeometry,
+                        (Some(_), Some(_), Some(_), None) => Reason::MissingStatus,
+                    };
+
+                    let position = reader.buffer_position();
+                    report.skip(reason, || format!("{} at byte {}", entry, position));
+
+                    return Ok(None);
                 }
             }
-            Ok(Event::Text(e)) => match state {
-                State::None => (),
-                State::Hoofdadres => (),
-                State::Identificatie => {
-                    let string = unsafe { std::str::from_utf8_unchecked(&e) };
-                    identificatie = Some(string.parse().unwrap());
-                    state = State::None;
-                }
-                State::Point => {
-                    let string = unsafe { std::str::from_utf8_unchecked(&e) };
-                    geopunt = Some(Geopunt::from_str(string).unwrap());
-                    state = State::None;
-                }
-                State::Polygon => {
-                    let string = unsafe { std::str::from_utf8_unchecked(&e) };
-                    let centroid = PosList::from_str(string).unwrap().centroid;
-                    geopunt = Some(Geopunt {
-                        x: centroid.0,
-                        y: centroid.1,
-                    });
-                    state = State::None;
+            Ok(Event::Text(e)) => {
+                let position = reader.buffer_position();
+
+                match state {
+                    State::None => (),
+                    State::Identificatie => {
+                        let field = "identificatie";
+                        identificatie = Some(parse_text(entry, position, field, &e, str::parse)?);
+                        state = State::None;
+                    }
+                    State::Hoofdadres => (),
+                    State::Nummeraanduiding => {
+                        let field = "hoofdadres";
+                        hoofdadres = Some(parse_text(entry, position, field, &e, str::parse)?);
+                        state = State::None;
+                    }
+                    State::Point => {
+                        let field = "gml:pos";
+                        geopunt = Some(parse_text(entry, position, field, &e, Geopunt::from_str)?);
+                        state = State::None;
+                    }
+                    State::Polygon => {
+                        let field = "gml:posList";
+                        let pos_list = parse_text(entry, position, field, &e, PosList::from_str)?;
+                        let centroid = pos_list.centroid;
+                        geopunt = Some(Geopunt {
+                            x: centroid.0,
+                            y: centroid.1,
+                        });
+                        state = State::None;
+                    }
+                    State::Status => {
+                        status = Some(parse_text(entry, position, "status", &e, str::parse)?);
+                        state = State::None;
+                    }
+                    State::Voorkomen => {
+                        let field = "voorkomenidentificatie";
+                        voorkomen.identificatie =
+                            parse_text(entry, position, field, &e, str::parse)?;
+                        state = State::None;
+                    }
+                    State::BeginGeldigheid => {
+                        let field = "beginGeldigheid";
+                        let date =
+                            parse_text(entry, position, field, &e, |s| parse_date(s).ok_or(()))?;
+                        voorkomen.begin_geldigheid = Some(date);
+                        state = State::None;
+                    }
+                    State::EindGeldigheid => {
+                        let field = "eindGeldigheid";
+                        let date =
+                            parse_text(entry, position, field, &e, |s| parse_date(s).ok_or(()))?;
+                        voorkomen.eind_geldigheid = Some(date);
+                        state = State::None;
+                    }
                 }
-            },
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            Ok(Event::Eof) => return None,
+            }
+            Err(e) => return Err(Error::xml(entry, reader.buffer_position(), e)),
+            Ok(Event::Eof) => {
+                let error = quick_xml::Error::UnexpectedEof("Objecten:Verblijfsobject".into());
+                return Err(Error::xml(entry, reader.buffer_position(), error));
+            }
             _ => (),
         }
 
         buf.clear();
-
-        if let (Some(identificatie), Some(geopunt)) = (identificatie, geopunt) {
-            return Some(Verblijfsobject {
-                identificatie,
-                geopunt,
-            });
-        }
     }
 }
 
@@ -221,11 +368,11 @@ impl std::str::FromStr for Geopunt {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut it = s.split(' ');
 
-        let x_string = it.next().unwrap();
-        let y_string = it.next().unwrap();
+        let x_string = it.next().ok_or(())?;
+        let y_string = it.next().ok_or(())?;
 
-        let x: f64 = x_string.parse().unwrap();
-        let y: f64 = y_string.parse().unwrap();
+        let x: f64 = x_string.parse().map_err(|_| ())?;
+        let y: f64 = y_string.parse().map_err(|_| ())?;
 
         Ok(Geopunt { x, y })
     }
@@ -240,20 +387,22 @@ impl std::str::FromStr for PosList {
     type Err = ();
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut values = s.split_ascii_whitespace().map(|x| {
-            let y: f64 = x.parse().unwrap();
-            y
-        });
+        let mut values = s.split_ascii_whitespace().map(|x| x.parse::<f64>());
 
         let mut points = Vec::new();
 
         while let Some(x) = values.next() {
-            let y = values.next().unwrap();
-            let _ = values.next().unwrap();
+            let x = x.map_err(|_| ())?;
+            let y = values.next().ok_or(())?.map_err(|_| ())?;
+            let _ = values.next().ok_or(())?.map_err(|_| ())?;
 
             points.push((x, y));
         }
 
+        if points.is_empty() {
+            return Err(());
+        }
+
         let point = centroid(&points);
 
         Ok(PosList { centroid: point })
@@ -313,8 +462,90 @@ mod test {
         "#;
 
         let mut reader = quick_xml::Reader::from_str(input);
-        let object: Verblijfsobject = parse_manual_help(&mut reader, &mut Vec::new()).unwrap();
+        let object: Verblijfsobject = parse_manual_help(
+            &mut reader,
+            &mut Vec::new(),
+            "input",
+            &mut Report::default(),
+        )
+        .unwrap()
+        .unwrap();
 
         dbg!(&object);
     }
+
+    #[test]
+    fn invalid_geopunt() {
+        let input = r#"
+            <Objecten:Verblijfsobject>
+                <Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">0363010000000001</Objecten:identificatie>
+                <Objecten:heeftAlsHoofdadres>
+                    <Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">0363200000123456</Objecten-ref:NummeraanduidingRef>
+                </Objecten:heeftAlsHoofdadres>
+                <gml:pos>121000.0 north</gml:pos>
+            </Objecten:Verblijfsobject>
+        "#;
+
+        match parse_manual_str(input) {
+            Err(Error::InvalidValue { field, text, .. }) => {
+                assert_eq!("gml:pos", field);
+                assert_eq!("121000.0 north", text);
+            }
+            other => panic!("expected an invalid gml:pos, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let input = r#"
+            <Objecten:Verblijfsobject>
+                <Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">0363010000000001</Objecten:identificatie>
+                <Objecten:heeftAlsHoofdadres>
+                    <Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">0363200000000001</Objecten-ref:NummeraanduidingRef>
+                </Objecten:heeftAlsHoofdadres>
+                <gml:posList>121000.0 487000.0</gml:posList>
+            </Objecten:Verblijfsobject>
+            <Objecten:Verblijfsobject>
+                <Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">0363010000000002</Objecten:identificatie>
+                <gml:pos>121000.0 487000.0 0.0</gml:pos>
+            </Objecten:Verblijfsobject>
+            <Objecten:Verblijfsobject>
+                <Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">0363010000000003</Objecten:identificatie>
+                <Objecten:heeftAlsHoofdadres>
+                    <Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">0363200000000003</Objecten-ref:NummeraanduidingRef>
+                </Objecten:heeftAlsHoofdadres>
+                <gml:pos>121000.0 487000.0 0.0</gml:pos>
+                <Objecten:status>Verblijfsobject in gebruik</Objecten:status>
+            </Objecten:Verblijfsobject>
+            <Objecten:Verblijfsobject>
+                <Objecten:heeftAlsHoofdadres>
+                    <Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">0363200000000004</Objecten-ref:NummeraanduidingRef>
+                </Objecten:heeftAlsHoofdadres>
+                <gml:pos>121000.0 487000.0 0.0</gml:pos>
+            </Objecten:Verblijfsobject>
+            <Objecten:Verblijfsobject>
+                <Objecten:identificatie domein="NL.IMBAG.Verblijfsobject">0363010000000005</Objecten:identificatie>
+                <Objecten:heeftAlsHoofdadres>
+                    <Objecten-ref:NummeraanduidingRef domein="NL.IMBAG.Nummeraanduiding">0363200000000005</Objecten-ref:NummeraanduidingRef>
+                </Objecten:heeftAlsHoofdadres>
+                <gml:pos>121000.0 487000.0 0.0</gml:pos>
+            </Objecten:Verblijfsobject>
+        "#;
+
+        assert!(parse_manual_str(input).is_err());
+
+        let mut result = Verblijfsobjecten::default();
+        parse_manual_step(input.as_bytes(), "input", Mode::Lenient, &mut result).unwrap();
+
+        assert_eq!(vec![363010000000003], result.identificatie);
+        assert_eq!(vec![363200000000003], result.postcode_id);
+
+        let report = &result.report;
+        let invalid = report.get(Reason::InvalidValue("gml:posList")).unwrap();
+        assert_eq!(1, invalid.count);
+        assert_eq!(1, report.get(Reason::MissingHoofdadres).unwrap().count);
+        assert_eq!(1, report.get(Reason::MissingIdentificatie).unwrap().count);
+        assert_eq!(1, report.get(Reason::MissingStatus).unwrap().count);
+        assert_eq!(vec![Status::InGebruik], result.status);
+    }
 }
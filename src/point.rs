@@ -1,9 +1,157 @@
+use crate::crs::RdPoint;
+use crate::memory_mapped_slice::Pod;
+
+/// How a location in RD New is stored in the index file
+///
+/// There are two representations, both 8 bytes per point: `Point` stores meters as `f32`, which
+/// at RD magnitudes (up to ~600 000 m) is only precise to about 6 cm. `MillimeterPoint` stores
+/// millimeters as `i32`, which represents the coordinates of the extract exactly, and compares
+/// distances exactly.
+///
+/// # Safety
+///
+/// Implementors must consist of two 4-byte fields, so that the stored types that contain them
+/// (e.g. `GridEntry`) have no padding.
+pub unsafe trait Coordinates: Pod + PartialEq + std::fmt::Debug {
+    /// Identifies the representation in the header of the index file
+    const KIND: u32;
+
+    /// A squared distance. For integer coordinates this is an integer, so comparisons are exact.
+    type SquaredDistance: Copy + PartialOrd + std::fmt::Debug;
+
+    fn from_rd(point: RdPoint) -> Self;
+
+    fn to_rd(self) -> RdPoint;
+
+    /// The (approximate) `f32` point, e.g. to find the cell of a grid that this point is in
+    fn to_point(self) -> Point {
+        Point::from(self.to_rd())
+    }
+
+    fn squared_distance_to(&self, other: &Self) -> Self::SquaredDistance;
+
+    /// The square of `meters`, so that `squared_distance_to(..) <= squared(meters)` means "within
+    /// `meters`"
+    fn squared(meters: f32) -> Self::SquaredDistance;
+
+    /// The distance in meters
+    fn distance(squared: Self::SquaredDistance) -> f32;
+
+    fn cmp_squared(a: &Self::SquaredDistance, b: &Self::SquaredDistance) -> std::cmp::Ordering {
+        a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal)
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
 pub struct Point {
     pub x: f32,
     pub y: f32,
 }
 
+// two f32 fields, no padding
+unsafe impl Pod for Point {}
+
+unsafe impl Coordinates for Point {
+    const KIND: u32 = 0;
+
+    type SquaredDistance = f32;
+
+    fn from_rd(point: RdPoint) -> Self {
+        Point::from(point)
+    }
+
+    fn to_rd(self) -> RdPoint {
+        RdPoint::from(self)
+    }
+
+    fn to_point(self) -> Point {
+        self
+    }
+
+    fn squared_distance_to(&self, other: &Self) -> f32 {
+        (self.x - other.x).powi(2) + (self.y - other.y).powi(2)
+    }
+
+    fn squared(meters: f32) -> f32 {
+        meters * meters
+    }
+
+    fn distance(squared: f32) -> f32 {
+        squared.sqrt()
+    }
+
+    fn cmp_squared(a: &f32, b: &f32) -> std::cmp::Ordering {
+        a.total_cmp(b)
+    }
+}
+
+/// A point in RD New, in whole millimeters
+///
+/// The extract gives coordinates in meters with three decimals, so they round-trip exactly. The
+/// largest RD coordinates (~630 000 000 mm) fit easily in an `i32`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(C)]
+pub struct MillimeterPoint {
+    pub x: i32,
+    pub y: i32,
+}
+
+// two i32 fields, no padding
+unsafe impl Pod for MillimeterPoint {}
+
+impl MillimeterPoint {
+    pub const fn new(x: i32, y: i32) -> Self {
+        Self { x, y }
+    }
+
+    /// Round coordinates in meters to the nearest millimeter
+    pub fn from_meters(x: f64, y: f64) -> Self {
+        Self {
+            x: (x * 1000.0).round() as i32,
+            y: (y * 1000.0).round() as i32,
+        }
+    }
+}
+
+unsafe impl Coordinates for MillimeterPoint {
+    const KIND: u32 = 1;
+
+    type SquaredDistance = i64;
+
+    fn from_rd(point: RdPoint) -> Self {
+        Self::from_meters(point.x, point.y)
+    }
+
+    fn to_rd(self) -> RdPoint {
+        RdPoint::new(self.x as f64 / 1000.0, self.y as f64 / 1000.0)
+    }
+
+    fn squared_distance_to(&self, other: &Self) -> i64 {
+        // the differences are at most ~2^30, so their squares and the sum fit in an i64
+        let dx = self.x as i64 - other.x as i64;
+        let dy = self.y as i64 - other.y as i64;
+
+        dx * dx + dy * dy
+    }
+
+    fn squared(meters: f32) -> i64 {
+        // round to micrometers first, so e.g. `0.01f32` (really 0.00999999977) is 10 mm. A
+        // squared distance is an integer, so rounding down does not change the comparison
+        let millimeters = (meters as f64 * 1_000_000.0).round() / 1000.0;
+
+        (millimeters * millimeters).floor() as i64
+    }
+
+    fn distance(squared: i64) -> f32 {
+        ((squared as f64).sqrt() / 1000.0) as f32
+    }
+
+    fn cmp_squared(a: &i64, b: &i64) -> std::cmp::Ordering {
+        a.cmp(b)
+    }
+}
+
 impl Point {
     pub const fn new(x: f32, y: f32) -> Self {
         Self { x, y }
@@ -35,3 +183,106 @@ impl std::str::FromStr for Point {
         })
     }
 }
+
+/// An axis-aligned box around a set of points
+///
+/// The empty box has its minimum at positive and its maximum at negative infinity, so it
+/// intersects nothing, and extending it with a point gives the box around just that point.
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(C)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+// two `Point` fields, no padding
+unsafe impl Pod for BoundingBox {}
+
+impl BoundingBox {
+    pub const EMPTY: Self = Self {
+        min: Point::new(f32::INFINITY, f32::INFINITY),
+        max: Point::new(f32::NEG_INFINITY, f32::NEG_INFINITY),
+    };
+
+    /// The square with sides of `2 * radius` centered on `center`
+    pub fn around(center: Point, radius: f32) -> Self {
+        Self {
+            min: Point::new(center.x - radius, center.y - radius),
+            max: Point::new(center.x + radius, center.y + radius),
+        }
+    }
+
+    pub fn from_points(points: &[Point]) -> Self {
+        let mut result = Self::EMPTY;
+
+        for point in points {
+            result.extend(*point);
+        }
+
+        result
+    }
+
+    pub fn extend(&mut self, point: Point) {
+        self.min.x = self.min.x.min(point.x);
+        self.min.y = self.min.y.min(point.y);
+        self.max.x = self.max.x.max(point.x);
+        self.max.y = self.max.y.max(point.y);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y
+    }
+
+    pub fn contains(&self, point: &Point) -> bool {
+        (self.min.x..=self.max.x).contains(&point.x) && (self.min.y..=self.max.y).contains(&point.y)
+    }
+
+    pub fn intersects(&self, other: &BoundingBox) -> bool {
+        self.min.x <= other.max.x
+            && other.min.x <= self.max.x
+            && self.min.y <= other.max.y
+            && other.min.y <= self.max.y
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn empty_bounding_box() {
+        let other = BoundingBox::around(Point::new(0.0, 0.0), 1e9);
+
+        assert!(BoundingBox::EMPTY.is_empty());
+        assert!(!BoundingBox::EMPTY.intersects(&other));
+        assert!(!other.intersects(&BoundingBox::EMPTY));
+    }
+
+    #[test]
+    fn millimeters() {
+        let point = MillimeterPoint::from_rd(RdPoint::new(233_392.425, 581_908.265));
+        assert_eq!(MillimeterPoint::new(233_392_425, 581_908_265), point);
+        assert_eq!(RdPoint::new(233_392.425, 581_908.265), point.to_rd());
+
+        // f32 cannot represent these coordinates
+        assert_ne!(233_392.425, Point::from_rd(point.to_rd()).x as f64);
+
+        let other = MillimeterPoint::new(233_392_425 + 3_000, 581_908_265 + 4_000);
+        assert_eq!(25_000_000, point.squared_distance_to(&other));
+        assert!(point.squared_distance_to(&other) <= MillimeterPoint::squared(5.0));
+        assert!(point.squared_distance_to(&other) > MillimeterPoint::squared(4.999));
+        assert_eq!(
+            5.0,
+            MillimeterPoint::distance(point.squared_distance_to(&other))
+        );
+    }
+
+    #[test]
+    fn bounding_box_intersects() {
+        let bbox = BoundingBox::from_points(&[Point::new(0.0, 0.0), Point::new(10.0, 5.0)]);
+
+        assert_eq!(Point::new(10.0, 5.0), bbox.max);
+        assert!(bbox.intersects(&BoundingBox::around(Point::new(12.0, 7.0), 2.0)));
+        assert!(!bbox.intersects(&BoundingBox::around(Point::new(12.0, 7.0), 1.5)));
+    }
+}
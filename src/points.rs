@@ -0,0 +1,297 @@
+use crate::error::Error;
+use crate::index_file::{IndexFile, IndexFileWriter};
+use crate::memory_mapped_slice::{MemoryMappedSlice, Range};
+use crate::point::{BoundingBox, Coordinates, Point};
+use crate::postcode::Postcode;
+
+pub const POINTS_SECTION: &str = "points";
+pub const SLICES_SECTION: &str = "slices";
+pub const BOUNDING_BOXES_SECTION: &str = "bounding-boxes";
+pub const POSTCODES_SECTION: &str = "postcodes";
+
+/// Which postcodes get an entry in the slices and bounding boxes sections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostcodeLayout {
+    /// Every valid postcode, indexed by `Postcode::as_dense_index` (~6.1M entries)
+    Dense,
+    /// Only the postcodes that occur (~470k entries). Their sorted list is stored in a separate
+    /// section, and looking up a postcode is a binary search in that list.
+    Sparse,
+}
+
+/// The points of all postcodes, grouped by postcode, while generating the index file
+///
+/// Built with a counting sort: count the points of every postcode, turn the counts into
+/// `Range`s, and then put every point directly in its final position. So all
+/// points are stored in one allocation, in the order that they are written to the index file.
+pub struct PostcodePoints<P: Coordinates = Point> {
+    /// the range in `points`, indexed by `Postcode::as_dense_index`
+    slices: Vec<Range>,
+    points: Vec<P>,
+}
+
+impl<P: Coordinates> PostcodePoints<P> {
+    /// Group `items` by postcode. Within a postcode, the points keep the order of `items`.
+    ///
+    /// `items` is iterated twice. Postcodes that are not between `Postcode::MIN` and
+    /// `Postcode::MAX` can never be queried, so their points are dropped. The Nummeraanduiding
+    /// parser already skips (and reports) such postcodes, so this only affects other input.
+    pub fn new<I>(items: I) -> Self
+    where
+        I: Iterator<Item = (Postcode, P)> + Clone,
+    {
+        let mut slices = vec![Range::default(); Postcode::DENSE_COUNT];
+
+        for (postcode, _) in items.clone() {
+            if let Some(index) = postcode.as_dense_index() {
+                slices[index].length += 1;
+            }
+        }
+
+        let mut start = 0;
+        for slice in slices.iter_mut() {
+            slice.start = start;
+            start += slice.length;
+        }
+
+        let mut next = slices.iter().map(|slice| slice.start).collect::<Vec<_>>();
+        // every slot is overwritten below, the first point is just a placeholder
+        let mut points = match items.clone().next() {
+            None => Vec::new(),
+            Some((_, first)) => vec![first; start as usize],
+        };
+
+        for (postcode, point) in items {
+            if let Some(index) = postcode.as_dense_index() {
+                let position = &mut next[index];
+
+                points[*position as usize] = point;
+                *position += 1;
+            }
+        }
+
+        Self { slices, points }
+    }
+
+    /// The points with the given postcode
+    pub fn get(&self, postcode: Postcode) -> &[P] {
+        match postcode.as_dense_index() {
+            None => &[],
+            Some(index) => self.slices[index].of(&self.points),
+        }
+    }
+
+    /// All valid postcodes in order, with their points
+    pub fn iter(&self) -> impl Iterator<Item = (Postcode, &[P])> + Clone {
+        self.slices
+            .iter()
+            .enumerate()
+            .map(move |(index, slice)| (Postcode::from_dense_index(index), slice.of(&self.points)))
+    }
+}
+
+/// The points of all postcodes
+///
+/// Stored as three arrays: one with all points, one that contains a `(start, length)` slice
+/// into the points array for every postcode, and one with the bounding box of every postcode.
+/// In the sparse layout, a fourth array contains the postcode of every slice.
+pub struct Points<P: Coordinates = Point> {
+    points: MemoryMappedSlice<P>,
+    slices: MemoryMappedSlice<Range>,
+    bounding_boxes: MemoryMappedSlice<BoundingBox>,
+    /// `None` for the dense layout
+    postcodes: Option<MemoryMappedSlice<u32>>,
+}
+
+impl<P: Coordinates> Points<P> {
+    /// Load the sections that `write_sections` wrote
+    pub fn from_index_file(index_file: &IndexFile) -> Result<Self, Error> {
+        let postcodes = if index_file.has_section(POSTCODES_SECTION) {
+            Some(index_file.section(POSTCODES_SECTION)?)
+        } else {
+            None
+        };
+
+        let index = Self {
+            points: index_file.section(POINTS_SECTION)?,
+            slices: index_file.section(SLICES_SECTION)?,
+            bounding_boxes: index_file.section(BOUNDING_BOXES_SECTION)?,
+            postcodes,
+        };
+
+        let expected = match &index.postcodes {
+            None => Postcode::DENSE_COUNT,
+            Some(postcodes) => postcodes.len(),
+        };
+
+        for (name, length) in [
+            (SLICES_SECTION, index.slices.len()),
+            (BOUNDING_BOXES_SECTION, index.bounding_boxes.len()),
+        ] {
+            if length != expected {
+                return Err(Error::InvalidIndex {
+                    path: index_file.path().display().to_string(),
+                    message: format!(
+                        "section {:?} has {} entries, expected {}",
+                        name, length, expected
+                    ),
+                });
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// Write the points in the given layout
+    pub fn write_sections(
+        writer: &mut IndexFileWriter,
+        postcode_points: &PostcodePoints<P>,
+        layout: PostcodeLayout,
+    ) -> std::io::Result<()> {
+        // the points are already grouped by postcode, so they are written as-is
+        let (postcodes, slices): (Vec<u32>, Vec<Range>) = postcode_points
+            .slices
+            .iter()
+            .enumerate()
+            .filter(|(_, slice)| layout == PostcodeLayout::Dense || slice.length > 0)
+            .map(|(index, slice)| (Postcode::from_dense_index(index).as_u32(), *slice))
+            .unzip();
+
+        let bounding_boxes: Vec<BoundingBox> = slices
+            .iter()
+            .map(|slice| {
+                let mut bounding_box = BoundingBox::EMPTY;
+                for point in slice.of(&postcode_points.points) {
+                    bounding_box.extend(point.to_point());
+                }
+
+                bounding_box
+            })
+            .collect();
+
+        writer.write_section(POINTS_SECTION, &postcode_points.points)?;
+        writer.write_section(SLICES_SECTION, &slices)?;
+        writer.write_section(BOUNDING_BOXES_SECTION, &bounding_boxes)?;
+
+        if layout == PostcodeLayout::Sparse {
+            writer.write_section(POSTCODES_SECTION, &postcodes)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn layout(&self) -> PostcodeLayout {
+        match self.postcodes {
+            None => PostcodeLayout::Dense,
+            Some(_) => PostcodeLayout::Sparse,
+        }
+    }
+
+    /// The position of `postcode` in the slices and bounding boxes
+    fn position(&self, postcode: Postcode) -> Option<usize> {
+        match &self.postcodes {
+            None => postcode.as_dense_index(),
+            Some(postcodes) => postcodes.as_slice().binary_search(&postcode.as_u32()).ok(),
+        }
+    }
+
+    fn postcode_at(&self, position: usize) -> Postcode {
+        match &self.postcodes {
+            None => Postcode::from_dense_index(position),
+            Some(postcodes) => Postcode::from_u32(postcodes.as_slice()[position]),
+        }
+    }
+
+    /// The bounding box of the points with the given postcode
+    pub fn bounding_box(&self, postcode: Postcode) -> BoundingBox {
+        match self.position(postcode) {
+            None => BoundingBox::EMPTY,
+            Some(position) => self.bounding_boxes.as_slice()[position],
+        }
+    }
+
+    /// The bounding box of every postcode, in the order of `iterate_postcodes`
+    pub fn bounding_boxes(&self) -> &[BoundingBox] {
+        self.bounding_boxes.as_slice()
+    }
+
+    /// The points with the given postcode
+    pub fn get(&self, postcode: Postcode) -> &[P] {
+        match self.position(postcode) {
+            None => &[],
+            Some(position) => self.slices.as_slice()[position].of(self.points.as_slice()),
+        }
+    }
+
+    /// All postcodes in order, with their points. In the sparse layout, that is only the
+    /// postcodes that have at least one point.
+    pub fn iterate_postcodes(&self) -> impl Iterator<Item = (Postcode, &[P])> {
+        let slices = self.slices.as_slice();
+        let points = self.points.as_slice();
+
+        (0..slices.len()).map(move |position| {
+            let postcode = self.postcode_at(position);
+
+            (postcode, slices[position].of(points))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn layouts() {
+        let postcode = |input| Postcode::try_from(input).unwrap();
+
+        let postcode_points = PostcodePoints::new(
+            vec![
+                (postcode("9999ZZ"), Point::new(3.0, 4.0)),
+                (postcode("1012BS"), Point::new(1.0, 2.0)),
+                (postcode("9999ZZ"), Point::new(5.0, 6.0)),
+                (postcode("0999ZZ"), Point::new(7.0, 8.0)),
+            ]
+            .into_iter(),
+        );
+
+        assert_eq!(
+            &[Point::new(3.0, 4.0), Point::new(5.0, 6.0)],
+            postcode_points.get(postcode("9999ZZ"))
+        );
+        assert!(postcode_points.get(postcode("0999ZZ")).is_empty());
+        assert_eq!(Postcode::DENSE_COUNT, postcode_points.iter().count());
+
+        for layout in [PostcodeLayout::Dense, PostcodeLayout::Sparse] {
+            let path = std::env::temp_dir().join(format!("bagextract-points-{:?}.bin", layout));
+
+            let mut writer = IndexFileWriter::create(&path, 0, Point::KIND).unwrap();
+            Points::write_sections(&mut writer, &postcode_points, layout).unwrap();
+            writer.finish().unwrap();
+
+            let index_file = IndexFile::open(&path).unwrap();
+            let points = Points::<Point>::from_index_file(&index_file).unwrap();
+
+            assert_eq!(layout, points.layout());
+            assert_eq!(&[Point::new(1.0, 2.0)], points.get(postcode("1012BS")));
+            assert_eq!(2, points.get(postcode("9999ZZ")).len());
+            assert!(points.get(postcode("1012BT")).is_empty());
+            assert!(points.bounding_box(postcode("1012BT")).is_empty());
+            assert_eq!(
+                Point::new(5.0, 6.0),
+                points.bounding_box(postcode("9999ZZ")).max
+            );
+
+            let occurring: Vec<Postcode> = points
+                .iterate_postcodes()
+                .filter(|(_, points)| !points.is_empty())
+                .map(|(postcode, _)| postcode)
+                .collect();
+            assert_eq!(vec![postcode("1012BS"), postcode("9999ZZ")], occurring);
+        }
+
+        let sparse = std::env::temp_dir().join("bagextract-points-Sparse.bin");
+        let dense = std::env::temp_dir().join("bagextract-points-Dense.bin");
+        assert!(sparse.metadata().unwrap().len() < dense.metadata().unwrap().len() / 100);
+    }
+}
@@ -7,7 +7,7 @@ pub struct Postcode {
 
 impl PartialOrd for Postcode {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.as_u32().partial_cmp(&other.as_u32())
+        Some(self.cmp(other))
     }
 }
 
@@ -21,6 +21,9 @@ impl Postcode {
     pub const MIN: Self = Self::new(1000, b'A', b'A');
     pub const MAX: Self = Self::new(9999, b'Z', b'Z');
 
+    /// The number of valid postcodes: `1000AA` up to and including `9999ZZ`
+    pub const DENSE_COUNT: usize = 9000 * 26 * 26;
+
     #[inline]
     pub const fn new(digits: u16, letter1: u8, letter2: u8) -> Self {
         let digits = digits as u32;
@@ -69,6 +72,34 @@ impl Postcode {
         Self::as_u32(self) as usize
     }
 
+    /// The index of this postcode among the valid postcodes, in `0..DENSE_COUNT`
+    ///
+    /// Unlike `as_index`, there are no gaps for digits below 1000 or unused letter values, so an
+    /// array indexed by this is about a third of the size. `None` for a postcode that is not
+    /// between `MIN` and `MAX`.
+    pub const fn as_dense_index(self) -> Option<usize> {
+        let (digits, letter1, letter2) = self.components();
+
+        if digits < 1000 || digits > 9999 || letter1 > b'Z' || letter2 > b'Z' {
+            return None;
+        }
+
+        let digits = (digits - 1000) as usize;
+        let letter1 = (letter1 - b'A') as usize;
+        let letter2 = (letter2 - b'A') as usize;
+
+        Some((digits * 26 + letter1) * 26 + letter2)
+    }
+
+    /// The inverse of `as_dense_index`. `index` must be smaller than `DENSE_COUNT`
+    pub const fn from_dense_index(index: usize) -> Self {
+        let digits = (index / (26 * 26)) as u16 + 1000;
+        let letter1 = ((index / 26) % 26) as u8 + b'A';
+        let letter2 = (index % 26) as u8 + b'A';
+
+        Self::new(digits, letter1, letter2)
+    }
+
     pub const fn components(self) -> (u16, u8, u8) {
         let input = self.as_u32();
         let digits = (input >> 10) as u16;
@@ -103,14 +134,16 @@ impl TryFrom<&str> for Postcode {
             return Err(());
         }
 
-        let digits: u16 = match &value[..4].parse() {
-            Ok(v) => *v,
-            Err(e) => {
-                panic!("{}", e);
-            }
-        };
+        let bytes = value.as_bytes();
 
-        let letters: [u8; 2] = value[4..6].as_bytes().try_into().unwrap();
+        if !bytes[..4].iter().all(u8::is_ascii_digit)
+            || !bytes[4..].iter().all(u8::is_ascii_uppercase)
+        {
+            return Err(());
+        }
+
+        let digits: u16 = value[..4].parse().map_err(|_| ())?;
+        let letters = [bytes[4], bytes[5]];
 
         Ok(Postcode::new(digits, letters[0], letters[1]))
     }
@@ -142,4 +175,36 @@ mod test {
 
         assert_eq!(input, &to_and_fro(input))
     }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(Err(()), Postcode::try_from("12A4BC"));
+        assert_eq!(Err(()), Postcode::try_from("1234bc"));
+        assert_eq!(Err(()), Postcode::try_from("1234B"));
+        assert_eq!(Err(()), Postcode::try_from("1234É"));
+    }
+
+    #[test]
+    fn dense_index() {
+        assert_eq!(Some(0), Postcode::MIN.as_dense_index());
+        assert_eq!(
+            Some(Postcode::DENSE_COUNT - 1),
+            Postcode::MAX.as_dense_index()
+        );
+
+        let goenga = Postcode::try_from("8628ET").unwrap();
+        let index = goenga.as_dense_index().unwrap();
+        assert_eq!(goenga, Postcode::from_dense_index(index));
+
+        // the dense index is in the same order as the postcodes themselves
+        let next = Postcode::try_from("8628EU").unwrap();
+        assert_eq!(Some(index + 1), next.as_dense_index());
+        assert!(goenga < next);
+
+        assert_eq!(None, Postcode::try_from("0999ZZ").unwrap().as_dense_index());
+        assert_eq!(
+            None,
+            Postcode::from_u32(Postcode::MAX.as_u32() + 1).as_dense_index()
+        );
+    }
 }
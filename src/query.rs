@@ -0,0 +1,296 @@
+// Find the postcodes that are close to a point
+//
+// This is phase 2 from the README: draw a bounding box around the target point, retain the
+// postcodes that intersect it, and check the actual points of the retained postcodes. The grid
+// makes sure that only the addresses in the cells around the target point are considered.
+
+use std::path::Path;
+
+use crate::adres::{Adres, Toevoeging};
+use crate::crs::RdPoint;
+use crate::error::Error;
+use crate::geocode::{AddressEntry, Addresses};
+use crate::grid::{self, Grid, GridEntry};
+use crate::index_file::{IndexFile, IndexFileWriter, INDEX_FILE};
+use crate::point::{Coordinates, Point};
+use crate::points::{Points, PostcodeLayout, PostcodePoints};
+use crate::postcode::Postcode;
+
+/// Everything that is needed to answer queries, as generated by the `generate` subcommand
+///
+/// The data is stored in RD New, as `P` (f32 meters or i32 millimeters). Queries accept any
+/// point that can be converted to RD New, so both `RdPoint` and `Wgs84Point` (and a stored
+/// `Point` or `MillimeterPoint`) can be used as the target.
+pub struct Index<P: Coordinates = Point> {
+    pub points: Points<P>,
+    pub grid: Grid<GridEntry<P>>,
+    pub addresses: Addresses<P>,
+    /// The date of the extract that the index was generated from, as `yyyymmdd`
+    pub extract_date: Option<u32>,
+}
+
+impl<P: Coordinates> Index<P> {
+    /// Load the index file that `create_file` wrote to `base_path`
+    pub fn from_dir(base_path: &Path) -> Result<Self, Error> {
+        Self::from_file(base_path.join(INDEX_FILE))
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_index_file(&IndexFile::open(path)?)
+    }
+
+    /// Load an opened index file, which must store its coordinates as `P`
+    pub fn from_index_file(index_file: &IndexFile) -> Result<Self, Error> {
+        if index_file.coordinates() != P::KIND {
+            return Err(Error::InvalidIndex {
+                path: index_file.path().display().to_string(),
+                message: format!(
+                    "coordinates are stored as kind {}, expected {}",
+                    index_file.coordinates(),
+                    P::KIND
+                ),
+            });
+        }
+
+        let index = Self {
+            points: Points::from_index_file(index_file)?,
+            grid: Grid::from_index_file(index_file)?,
+            addresses: Addresses::from_index_file(index_file)?,
+            extract_date: index_file.extract_date(),
+        };
+
+        Ok(index)
+    }
+
+    /// Write everything that the queries need to one index file
+    pub fn create_file(
+        path: impl AsRef<Path>,
+        extract_date: Option<u32>,
+        addresses: &[Adres],
+        postcode_points: &PostcodePoints<P>,
+        layout: PostcodeLayout,
+    ) -> std::io::Result<()> {
+        let mut writer = IndexFileWriter::create(path, extract_date.unwrap_or(0), P::KIND)?;
+
+        Points::write_sections(&mut writer, postcode_points, layout)?;
+        Grid::write_postcode_sections(&mut writer, postcode_points)?;
+        Addresses::<P>::write_sections(&mut writer, addresses)?;
+
+        writer.finish()
+    }
+
+    /// All postcodes that have at least one adres within `radius` meters of `target`
+    pub fn within_radius(&self, target: impl Into<RdPoint>, radius: f32) -> Vec<Postcode> {
+        within_radius(&self.grid, P::from_rd(target.into()), radius)
+    }
+
+    /// The `k` postcodes that are closest to `target`, and their distance to `target`
+    pub fn nearest_postcodes(&self, target: impl Into<RdPoint>, k: usize) -> Vec<(Postcode, f32)> {
+        nearest_postcodes(&self.points, &self.grid, P::from_rd(target.into()), k)
+    }
+
+    /// The adres that is closest to `target`, and its distance to `target`
+    pub fn nearest_address(&self, target: impl Into<RdPoint>) -> Option<(GridEntry<P>, f32)> {
+        self.grid
+            .nearest(P::from_rd(target.into()))
+            .map(|(entry, distance)| (*entry, distance))
+    }
+
+    /// The location (in RD New) of the adres with the given postcode and house number
+    pub fn geocode(
+        &self,
+        postcode: Postcode,
+        huisnummer: u32,
+        huisletter: Option<u8>,
+        toevoeging: Option<Toevoeging>,
+    ) -> Option<P> {
+        self.addresses
+            .geocode(postcode, huisnummer, huisletter, toevoeging)
+    }
+
+    /// The adres that is closest to `target`, and its distance to `target`
+    pub fn reverse_geocode(&self, target: impl Into<RdPoint>) -> Option<(AddressEntry<P>, f32)> {
+        self.addresses
+            .reverse_geocode(P::from_rd(target.into()))
+            .map(|(entry, distance)| (*entry, distance))
+    }
+}
+
+/// All postcodes that have at least one adres within `radius` meters of `target`
+///
+/// The result is sorted, and contains every postcode at most once.
+pub fn within_radius<P: Coordinates>(
+    grid: &Grid<GridEntry<P>>,
+    target: P,
+    radius: f32,
+) -> Vec<Postcode> {
+    let mut result: Vec<Postcode> = grid
+        .within_radius(target, radius)
+        .map(|entry| entry.postcode())
+        .filter(|postcode| (Postcode::MIN..=Postcode::MAX).contains(postcode))
+        .collect();
+
+    result.sort();
+    result.dedup();
+
+    result
+}
+
+/// The `k` postcodes that are closest to `target`, and their distance to `target`
+///
+/// The distance of a postcode is the distance to its closest adres. The result is sorted by
+/// distance, and is only shorter than `k` when there are fewer than `k` postcodes in total.
+pub fn nearest_postcodes<P: Coordinates>(
+    points: &Points<P>,
+    grid: &Grid<GridEntry<P>>,
+    target: P,
+    k: usize,
+) -> Vec<(Postcode, f32)> {
+    let squared_distance_to = |postcode: Postcode| {
+        points
+            .get(postcode)
+            .iter()
+            .map(|point| point.squared_distance_to(&target))
+            .min_by(P::cmp_squared)
+    };
+
+    if k == 0 {
+        return Vec::new();
+    }
+
+    let mut result = Vec::new();
+
+    for radius in grid::search_radii(target.to_point()) {
+        // every postcode that is not in `result` is at least `radius` meters away, so once we
+        // have `k` postcodes within `radius`, those are the `k` closest
+        result = within_radius(grid, target, radius)
+            .into_iter()
+            .filter_map(|postcode| Some((postcode, squared_distance_to(postcode)?)))
+            .collect();
+
+        if result.len() >= k {
+            break;
+        }
+    }
+
+    result.sort_by(|a, b| P::cmp_squared(&a.1, &b.1).then(a.0.cmp(&b.0)));
+    result.truncate(k);
+
+    result
+        .into_iter()
+        .map(|(postcode, squared)| (postcode, P::distance(squared)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::crs::Wgs84Point;
+    use crate::point::MillimeterPoint;
+
+    fn postcode(input: &str) -> Postcode {
+        Postcode::try_from(input).unwrap()
+    }
+
+    fn test_index(name: &str) -> Index {
+        let path = std::env::temp_dir().join(name);
+
+        let postcode_points = PostcodePoints::new(
+            vec![
+                (postcode("1012BS"), Point::new(121_000.0, 487_000.0)),
+                (postcode("1012BV"), Point::new(125_000.0, 487_000.0)),
+                (postcode("1012BV"), Point::new(121_030.0, 487_040.0)),
+                (postcode("1012CT"), Point::new(121_060.0, 487_000.0)),
+            ]
+            .into_iter(),
+        );
+
+        Index::create_file(&path, None, &[], &postcode_points, PostcodeLayout::Sparse).unwrap();
+        Index::from_file(&path).unwrap()
+    }
+
+    #[test]
+    fn radius() {
+        let index = test_index("bagextract-query-radius.bin");
+
+        let target = Point::new(121_000.0, 487_000.0);
+
+        assert_eq!(vec![postcode("1012BS")], index.within_radius(target, 10.0));
+        assert_eq!(
+            vec![postcode("1012BS"), postcode("1012BV")],
+            index.within_radius(target, 50.0)
+        );
+        assert_eq!(
+            vec![postcode("1012BS"), postcode("1012BV"), postcode("1012CT")],
+            index.within_radius(target, 60.0)
+        );
+    }
+
+    #[test]
+    fn nearest() {
+        let index = test_index("bagextract-query-nearest.bin");
+
+        let target = Point::new(121_000.0, 486_990.0);
+
+        assert_eq!(
+            vec![(postcode("1012BS"), 10.0), (postcode("1012BV"), 58.30952)],
+            index.nearest_postcodes(target, 2)
+        );
+        assert_eq!(3, index.nearest_postcodes(target, 5).len());
+
+        let (entry, distance) = index.nearest_address(target).unwrap();
+        assert_eq!(postcode("1012BS"), entry.postcode());
+        assert_eq!(10.0, distance);
+
+        let wgs84 = Wgs84Point::from(target);
+        let (entry, distance) = index.nearest_address(wgs84).unwrap();
+        assert_eq!(postcode("1012BS"), entry.postcode());
+        assert!((distance - 10.0).abs() < 1.0);
+
+        let far_away = Point::new(250_000.0, 600_000.0);
+        assert_eq!(
+            postcode("1012BV"),
+            index.nearest_postcodes(far_away, 1)[0].0
+        );
+    }
+
+    #[test]
+    fn millimeters() {
+        let path = std::env::temp_dir().join("bagextract-query-millimeters.bin");
+
+        // 1 mm apart: at these magnitudes, f32 cannot tell which one is closer
+        let postcode_points = PostcodePoints::new(
+            vec![
+                (
+                    postcode("1012BS"),
+                    MillimeterPoint::new(121_000_011, 487_000_000),
+                ),
+                (
+                    postcode("1012BV"),
+                    MillimeterPoint::new(121_000_010, 487_000_000),
+                ),
+            ]
+            .into_iter(),
+        );
+
+        Index::create_file(&path, None, &[], &postcode_points, PostcodeLayout::Dense).unwrap();
+        assert!(Index::<Point>::from_file(&path).is_err());
+
+        let index = Index::<MillimeterPoint>::from_file(&path).unwrap();
+
+        let target = MillimeterPoint::new(121_000_000, 487_000_000);
+        let nearest = index.nearest_postcodes(target, 2);
+        assert_eq!(vec![postcode("1012BV"), postcode("1012BS")], {
+            nearest
+                .iter()
+                .map(|(postcode, _)| *postcode)
+                .collect::<Vec<_>>()
+        });
+        assert_eq!(0.01, nearest[0].1);
+
+        let (entry, _) = index.nearest_address(target).unwrap();
+        assert_eq!(MillimeterPoint::new(121_000_010, 487_000_000), entry.point);
+
+        assert_eq!(vec![postcode("1012BV")], index.within_radius(target, 0.010));
+    }
+}
@@ -0,0 +1,324 @@
+// Keep track of the records that are skipped while parsing
+//
+// Not every record in the extract ends up in the output: some Verblijfsobjecten have no
+// hoofdadres, some Nummeraanduidingen have no postcode, and many records are an old voorkomen or
+// an object that does not exist (anymore, see `crate::status`). In lenient mode, records with malformed
+// values are skipped too (in strict mode they are an error). The report counts the skipped
+// records per reason, and keeps a couple of samples so they can be looked up in the extract.
+//
+// The sort-merge join also finds the Nummeraanduidingen that are not the hoofdadres of any
+// Verblijfsobject. Those are valid records, so they are counted separately from the skipped ones.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::Error;
+use crate::status::Status;
+
+pub const REPORT_FILE: &str = "report.json";
+
+/// The number of samples that is kept for every reason
+const MAX_SAMPLES: usize = 10;
+
+/// What to do with records that contain a malformed value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Stop with an error
+    Strict,
+    /// Skip the record, and add it to the report
+    Lenient,
+}
+
+/// Why a record was skipped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Reason {
+    /// A Verblijfsobject without a hoofdadres
+    MissingHoofdadres,
+    /// A Verblijfsobject without a gml:pos or gml:posList
+    MissingGeometry,
+    /// A Verblijfsobject or Nummeraanduiding without an identificatie
+    MissingIdentificatie,
+    /// A Nummeraanduiding without a postcode
+    MissingPostcode,
+    /// A Verblijfsobject or Nummeraanduiding without a status
+    MissingStatus,
+    /// A voorkomen that is not valid on the date of the extract, or that was corrected
+    NotCurrent,
+    /// An object with a status that is not selected with `--status`
+    Status(Status),
+    /// A Verblijfsobject whose hoofdadres is not one of the Nummeraanduidingen
+    UnknownNummeraanduiding,
+    /// A record with a malformed value for the given field (lenient mode only)
+    InvalidValue(&'static str),
+}
+
+impl std::fmt::Display for Reason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::MissingHoofdadres => write!(f, "missing hoofdadres"),
+            Reason::MissingGeometry => write!(f, "missing geometry"),
+            Reason::MissingIdentificatie => write!(f, "missing identificatie"),
+            Reason::MissingPostcode => write!(f, "missing postcode"),
+            Reason::MissingStatus => write!(f, "missing status"),
+            Reason::NotCurrent => write!(f, "not the current voorkomen"),
+            Reason::Status(status) => write!(f, "status {}", status),
+            Reason::UnknownNummeraanduiding => write!(f, "unknown nummeraanduiding"),
+            Reason::InvalidValue(field) => write!(f, "invalid {}", field),
+        }
+    }
+}
+
+/// A count of records, and a couple of samples
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Skipped {
+    pub count: u64,
+    pub samples: Vec<String>,
+}
+
+impl Skipped {
+    fn add(&mut self, other: Skipped) {
+        self.count += other.count;
+
+        let room = MAX_SAMPLES.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.into_iter().take(room));
+    }
+
+    /// Write `{"count": .., "samples": [..]}`, with the closing brace at `indent`
+    fn write_json<W: Write>(&self, writer: &mut W, indent: &str) -> std::io::Result<()> {
+        writeln!(writer, "{{")?;
+        writeln!(writer, "{}  \"count\": {},", indent, self.count)?;
+        write!(writer, "{}  \"samples\": [", indent)?;
+
+        for (j, sample) in self.samples.iter().enumerate() {
+            if j > 0 {
+                write!(writer, ", ")?;
+            }
+            write_json_string(writer, sample)?;
+        }
+
+        writeln!(writer, "]")?;
+        write!(writer, "{}}}", indent)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Report {
+    skipped: BTreeMap<Reason, Skipped>,
+    /// the Nummeraanduidingen that are not the hoofdadres of any Verblijfsobject, if they were
+    /// looked for (by the sort-merge join)
+    unreferenced: Option<Skipped>,
+}
+
+impl Report {
+    /// Record a skipped record. `sample` is only evaluated when it is actually kept.
+    pub fn skip(&mut self, reason: Reason, sample: impl FnOnce() -> String) {
+        let skipped = self.skipped.entry(reason).or_default();
+
+        skipped.count += 1;
+
+        if skipped.samples.len() < MAX_SAMPLES {
+            skipped.samples.push(sample());
+        }
+    }
+
+    /// Record a record that was skipped because of `error`
+    ///
+    /// Only malformed values can be skipped, so any other error is given back.
+    pub fn skip_invalid(&mut self, error: Error) -> Result<(), Error> {
+        match error {
+            Error::InvalidValue { field, .. } => {
+                self.skip(Reason::InvalidValue(field), || error.to_string());
+                Ok(())
+            }
+            other => Err(other),
+        }
+    }
+
+    /// Start counting the Nummeraanduidingen that are not the hoofdadres of any Verblijfsobject
+    pub fn count_unreferenced(&mut self) {
+        self.unreferenced.get_or_insert_with(Skipped::default);
+    }
+
+    /// Record a Nummeraanduiding that is not the hoofdadres of any Verblijfsobject
+    pub fn unreferenced(&mut self, sample: impl FnOnce() -> String) {
+        let unreferenced = self.unreferenced.get_or_insert_with(Skipped::default);
+
+        unreferenced.count += 1;
+
+        if unreferenced.samples.len() < MAX_SAMPLES {
+            unreferenced.samples.push(sample());
+        }
+    }
+
+    /// The unreferenced Nummeraanduidingen, or `None` when they were not counted
+    pub fn get_unreferenced(&self) -> Option<&Skipped> {
+        self.unreferenced.as_ref()
+    }
+
+    pub fn get(&self, reason: Reason) -> Option<&Skipped> {
+        self.skipped.get(&reason)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Reason, &Skipped)> {
+        self.skipped
+            .iter()
+            .map(|(reason, skipped)| (*reason, skipped))
+    }
+
+    pub fn merge(mut self, other: Self) -> Self {
+        for (reason, skipped) in other.skipped {
+            let entry = self.skipped.entry(reason).or_default();
+            entry.add(skipped);
+        }
+
+        if let Some(unreferenced) = other.unreferenced {
+            let entry = self.unreferenced.get_or_insert_with(Skipped::default);
+            entry.add(unreferenced);
+        }
+
+        self
+    }
+
+    /// Write the report as a JSON object, with a `count` and `samples` for every reason under
+    /// `skipped`, and for the `unreferenced nummeraanduidingen` if they were counted
+    pub fn write_json<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let unreferenced = self
+            .unreferenced
+            .iter()
+            .map(|skipped| ("unreferenced nummeraanduidingen".to_string(), skipped));
+        let entries = self
+            .iter()
+            .map(|(reason, skipped)| (reason.to_string(), skipped))
+            .chain(unreferenced);
+        let length = self.skipped.len() + self.unreferenced.iter().len();
+
+        writeln!(writer, "{{")?;
+
+        for (i, (name, skipped)) in entries.enumerate() {
+            let separator = if i + 1 < length { "," } else { "" };
+
+            write!(writer, "  ")?;
+            write_json_string(&mut writer, &name)?;
+            write!(writer, ": ")?;
+            skipped.write_json(&mut writer, "  ")?;
+            writeln!(writer, "{}", separator)?;
+        }
+
+        writeln!(writer, "}}")
+    }
+
+    pub fn write_json_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = std::fs::File::create(path)?;
+        let mut writer = std::io::BufWriter::new(file);
+
+        self.write_json(&mut writer)?;
+
+        writer.flush()
+    }
+}
+
+/// Write `value` as a quoted and escaped JSON string
+pub(crate) fn write_json_string<W: Write>(writer: &mut W, value: &str) -> std::io::Result<()> {
+    write!(writer, "\"")?;
+
+    for c in value.chars() {
+        match c {
+            '"' => write!(writer, "\\\"")?,
+            '\\' => write!(writer, "\\\\")?,
+            '\n' => write!(writer, "\\n")?,
+            '\r' => write!(writer, "\\r")?,
+            '\t' => write!(writer, "\\t")?,
+            c if (c as u32) < 0x20 => write!(writer, "\\u{:04x}", c as u32)?,
+            c => write!(writer, "{}", c)?,
+        }
+    }
+
+    write!(writer, "\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn samples() {
+        let mut a = Report::default();
+        let mut b = Report::default();
+
+        for i in 0..8 {
+            a.skip(Reason::MissingPostcode, || i.to_string());
+            b.skip(Reason::MissingPostcode, || (i + 8).to_string());
+        }
+        b.skip(Reason::MissingHoofdadres, || "hoofdadres".to_string());
+
+        let report = a.merge(b);
+
+        let skipped = report.get(Reason::MissingPostcode).unwrap();
+        assert_eq!(16, skipped.count);
+        assert_eq!(MAX_SAMPLES, skipped.samples.len());
+        assert_eq!("9", skipped.samples[9]);
+
+        assert_eq!(1, report.get(Reason::MissingHoofdadres).unwrap().count);
+        assert_eq!(None, report.get(Reason::MissingGeometry));
+    }
+
+    #[test]
+    fn json() {
+        let mut report = Report::default();
+        report.skip(Reason::MissingHoofdadres, || {
+            "vbo.zip/a.xml at byte 12".to_string()
+        });
+        report.skip(Reason::InvalidValue("gml:posList"), || {
+            "\"1 2\"\n".to_string()
+        });
+
+        let mut output = Vec::new();
+        report.write_json(&mut output).unwrap();
+
+        let expected = r#"{
+  "missing hoofdadres": {
+    "count": 1,
+    "samples": ["vbo.zip/a.xml at byte 12"]
+  },
+  "invalid gml:posList": {
+    "count": 1,
+    "samples": ["\"1 2\"\n"]
+  }
+}
+"#;
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn json_unreferenced() {
+        let mut report = Report::default();
+        report.skip(Reason::MissingStatus, || "1 in num.zip/a.xml".to_string());
+        report.unreferenced(|| "50".to_string());
+
+        let mut output = Vec::new();
+        Report::default()
+            .merge(report)
+            .write_json(&mut output)
+            .unwrap();
+
+        let expected = r#"{
+  "missing status": {
+    "count": 1,
+    "samples": ["1 in num.zip/a.xml"]
+  },
+  "unreferenced nummeraanduidingen": {
+    "count": 1,
+    "samples": ["50"]
+  }
+}
+"#;
+
+        assert_eq!(expected, String::from_utf8(output).unwrap());
+
+        let mut output = Vec::new();
+        Report::default().write_json(&mut output).unwrap();
+        assert_eq!("{\n}\n", String::from_utf8(output).unwrap());
+    }
+}
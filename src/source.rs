@@ -0,0 +1,382 @@
+// Locate the zip files with Verblijfsobjecten and Nummeraanduidingen
+//
+// The national extract (`lvbag-extract-nl.zip`) is a zip file that contains zip files, e.g.
+// `9999VBO08102021.zip` and `9999NUM08102021.zip`. Those inner zip files are stored without
+// compression, so we can read them straight from the outer file: an inner zip file is just a
+// range of bytes in the outer one. That saves unpacking 2+ GB of temporary files. (A compressed
+// inner zip file is still unpacked.)
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use zip::ZipArchive;
+
+use crate::error::Error;
+
+/// A zip archive with xml files
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZipSource {
+    /// A zip file on disk
+    File(PathBuf),
+    /// A zip file that is stored (without compression) inside of another zip file
+    Nested {
+        outer: PathBuf,
+        name: String,
+        start: u64,
+        length: u64,
+    },
+}
+
+impl ZipSource {
+    /// Find the inner zip file whose name contains `pattern` (e.g. "VBO") in the national extract
+    ///
+    /// An inner zip file that is compressed cannot be read in place. It is unpacked once, next to
+    /// the extract, and read from there.
+    pub fn find_in_extract(extract: &Path, pattern: &str) -> Result<Self, Error> {
+        let zip_error = |error| Error::Zip {
+            archive: extract.display().to_string(),
+            error,
+        };
+
+        let file = File::open(extract)?;
+        let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+
+        let mut found = None;
+
+        for i in 0..archive.len() {
+            let member = archive.by_index(i).map_err(zip_error)?;
+            let name = member.name();
+
+            if !(name.contains(pattern) && name.ends_with(".zip")) {
+                continue;
+            }
+
+            if found.is_some() {
+                let message = format!("multiple members of {:?} match {:?}", extract, pattern);
+                let error = std::io::Error::new(std::io::ErrorKind::InvalidData, message);
+                return Err(error.into());
+            }
+
+            found = Some(i);
+        }
+
+        let index = found.ok_or_else(|| {
+            let message = format!("no member of {:?} matches {:?}", extract, pattern);
+            std::io::Error::new(std::io::ErrorKind::NotFound, message)
+        })?;
+
+        let mut member = archive.by_index(index).map_err(zip_error)?;
+
+        if member.compression() == zip::CompressionMethod::Stored {
+            return Ok(ZipSource::Nested {
+                outer: extract.to_path_buf(),
+                name: member.name().to_string(),
+                start: member.data_start(),
+                length: member.size(),
+            });
+        }
+
+        let file_name = member.name().rsplit('/').next().unwrap_or_default();
+        let path = extract.with_file_name(file_name);
+
+        let unpacked = std::fs::metadata(&path).map(|metadata| metadata.len());
+        if unpacked.ok() != Some(member.size()) {
+            eprintln!(
+                "member {:?} is compressed, unpacking it to {:?}",
+                member.name(),
+                path
+            );
+
+            let mut tmp = path.clone().into_os_string();
+            tmp.push(".tmp");
+
+            std::io::copy(&mut member, &mut File::create(&tmp)?)?;
+            std::fs::rename(&tmp, &path)?;
+        }
+
+        Ok(ZipSource::File(path))
+    }
+
+    /// The VBO and NUM zip files in `path`
+    ///
+    /// `path` is either the national extract, or a directory with `vbo.zip` and `num.zip`
+    pub fn verblijfsobjecten_and_nummeraanduidingen(path: &Path) -> Result<(Self, Self), Error> {
+        if path.is_dir() {
+            Ok((
+                ZipSource::File(path.join("vbo.zip")),
+                ZipSource::File(path.join("num.zip")),
+            ))
+        } else {
+            Ok((
+                Self::find_in_extract(path, "VBO")?,
+                Self::find_in_extract(path, "NUM")?,
+            ))
+        }
+    }
+
+    /// The zip file with mutations in `path`
+    ///
+    /// `path` is either a mutation extract that contains a `9999MUT...` zip file, or a zip file
+    /// with the mutation xml files themselves
+    pub fn mutations(path: &Path) -> Result<Self, Error> {
+        let file = File::open(path)?;
+        let archive = ZipArchive::new(file).map_err(|error| Error::Zip {
+            archive: path.display().to_string(),
+            error,
+        })?;
+
+        if archive.file_names().any(|name| name.ends_with(".zip")) {
+            Self::find_in_extract(path, "MUT")
+        } else {
+            Ok(ZipSource::File(path.to_path_buf()))
+        }
+    }
+
+    /// The date of the extract as `yyyymmdd`, from the name of the zip file
+    ///
+    /// The zip files in the national extract are named like `9999VBO08102021.zip`, ending in the
+    /// date as `ddmmyyyy`. Other names (e.g. `vbo.zip`) give `None`.
+    pub fn extract_date(&self) -> Option<u32> {
+        let stem = self.stem()?;
+
+        parse_ddmmyyyy(stem.get(stem.len().checked_sub(8)?..)?)
+    }
+
+    /// The period of a mutation file as `yyyymmdd`, from its name
+    ///
+    /// Mutation files are named like `9999MUT08102021-09102021.zip`: they contain the mutations
+    /// from the first date up to the second one.
+    pub fn mutation_period(&self) -> Option<(u32, u32)> {
+        let stem = self.stem()?;
+        let period = stem.get(stem.len().checked_sub(17)?..)?;
+        let (from, to) = period.split_once('-')?;
+
+        Some((parse_ddmmyyyy(from)?, parse_ddmmyyyy(to)?))
+    }
+
+    /// The name of the zip file, without the directory and `.zip`
+    fn stem(&self) -> Option<&str> {
+        let name = match self {
+            ZipSource::File(path) => path.file_name()?.to_str()?,
+            ZipSource::Nested { name, .. } => name.rsplit('/').next()?,
+        };
+
+        name.strip_suffix(".zip")
+    }
+
+    pub fn open(&self) -> Result<ZipArchive<Window>, Error> {
+        let window = match self {
+            ZipSource::File(path) => {
+                let file = File::open(path)?;
+                let length = file.metadata()?.len();
+
+                Window::new(file, 0, length)?
+            }
+            ZipSource::Nested {
+                outer,
+                start,
+                length,
+                ..
+            } => Window::new(File::open(outer)?, *start, *length)?,
+        };
+
+        ZipArchive::new(window).map_err(|error| Error::Zip {
+            archive: self.to_string(),
+            error,
+        })
+    }
+}
+
+/// Parse a date as `ddmmyyyy` into `yyyymmdd`
+fn parse_ddmmyyyy(date: &str) -> Option<u32> {
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let day: u32 = date[0..2].parse().ok()?;
+    let month: u32 = date[2..4].parse().ok()?;
+    let year: u32 = date[4..8].parse().ok()?;
+
+    if !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+        return None;
+    }
+
+    Some(year * 10000 + month * 100 + day)
+}
+
+impl std::fmt::Display for ZipSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ZipSource::File(path) => write!(f, "{}", path.display()),
+            ZipSource::Nested { outer, name, .. } => write!(f, "{}/{}", outer.display(), name),
+        }
+    }
+}
+
+/// A range of bytes of a file, that behaves like a file by itself
+pub struct Window {
+    file: File,
+    start: u64,
+    length: u64,
+    position: u64,
+}
+
+impl Window {
+    pub fn new(mut file: File, start: u64, length: u64) -> std::io::Result<Self> {
+        file.seek(SeekFrom::Start(start))?;
+
+        Ok(Self {
+            file,
+            start,
+            length,
+            position: 0,
+        })
+    }
+}
+
+impl Read for Window {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.length.saturating_sub(self.position);
+        let limit = buf.len().min(remaining as usize);
+
+        let n = self.file.read(&mut buf[..limit])?;
+        self.position += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Seek for Window {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.length.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+
+        match position {
+            None => {
+                let message = "invalid seek to a negative or overflowing position";
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    message,
+                ))
+            }
+            Some(position) => {
+                self.file.seek(SeekFrom::Start(self.start + position))?;
+                self.position = position;
+
+                Ok(position)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_with(members: &[(&str, &[u8])], method: zip::CompressionMethod) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().compression_method(method);
+
+        for (name, contents) in members {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn nested() {
+        use zip::CompressionMethod::{Deflated, Stored};
+
+        let inner = zip_with(&[("hello.xml", b"<hello/>")], Deflated);
+        let outer = zip_with(
+            &[
+                ("Leveringsdocument.xml", b"<foo/>"),
+                ("9999VBO08102021.zip", &inner),
+            ],
+            Stored,
+        );
+
+        let path = std::env::temp_dir().join("bagextract-source-nested.zip");
+        std::fs::write(&path, outer).unwrap();
+
+        let source = ZipSource::find_in_extract(&path, "VBO").unwrap();
+        assert!(ZipSource::find_in_extract(&path, "NUM").is_err());
+        assert_eq!(Some(20211008), source.extract_date());
+        assert_eq!(None, ZipSource::File("vbo.zip".into()).extract_date());
+        assert_eq!(None, source.mutation_period());
+
+        let mutations = ZipSource::File("data/9999MUT08102021-09102021.zip".into());
+        assert_eq!(Some((20211008, 20211009)), mutations.mutation_period());
+        assert_eq!(Some(20211009), mutations.extract_date());
+        let mutations = ZipSource::File("9999MUT08102021-09132021.zip".into());
+        assert_eq!(None, mutations.mutation_period());
+
+        let mut archive = source.open().unwrap();
+        let mut contents = String::new();
+        archive
+            .by_name("hello.xml")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        assert_eq!("<hello/>", contents);
+    }
+
+    #[test]
+    fn nested_compressed() {
+        use zip::CompressionMethod::Deflated;
+
+        let inner = zip_with(&[("hello.xml", b"<hello/>")], Deflated);
+        let outer = zip_with(&[("9999VBO08102021.zip", &inner)], Deflated);
+
+        let dir = std::env::temp_dir().join("bagextract-source-nested-compressed");
+        std::fs::create_dir_all(&dir).unwrap();
+        let _ = std::fs::remove_file(dir.join("9999VBO08102021.zip"));
+
+        let path = dir.join("lvbag-extract-nl.zip");
+        std::fs::write(&path, outer).unwrap();
+
+        let source = ZipSource::find_in_extract(&path, "VBO").unwrap();
+        assert_eq!(ZipSource::File(dir.join("9999VBO08102021.zip")), source);
+        assert_eq!(Some(20211008), source.extract_date());
+        assert_eq!(
+            inner,
+            std::fs::read(dir.join("9999VBO08102021.zip")).unwrap()
+        );
+
+        let mut archive = source.open().unwrap();
+        assert!(archive.by_name("hello.xml").is_ok());
+    }
+
+    #[test]
+    fn parse_nested() {
+        use zip::CompressionMethod::{Deflated, Stored};
+
+        let xml = br#"
+            <Objecten:Nummeraanduiding>
+                <Objecten:identificatie domein="NL.IMBAG.Nummeraanduiding">0363200000123456</Objecten:identificatie>
+                <Objecten:huisnummer>12</Objecten:huisnummer>
+                <Objecten:postcode>1012BS</Objecten:postcode>
+                <Objecten:status>Naamgeving uitgegeven</Objecten:status>
+            </Objecten:Nummeraanduiding>
+        "#;
+
+        let inner = zip_with(&[("9999NUM08102021-000001.xml", xml)], Deflated);
+        let outer = zip_with(&[("9999NUM08102021.zip", &inner)], Stored);
+
+        let path = std::env::temp_dir().join("bagextract-source-parse-nested.zip");
+        std::fs::write(&path, outer).unwrap();
+
+        let source = ZipSource::find_in_extract(&path, "NUM").unwrap();
+        let postcodes = crate::parse_num::parse(&source, crate::report::Mode::Strict).unwrap();
+
+        assert_eq!(vec![363200000123456], postcodes.identificatie);
+    }
+}
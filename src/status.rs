@@ -0,0 +1,359 @@
+// The lifecycle of BAG objects: their status, and the period that a version is valid
+//
+// An object (e.g. a Verblijfsobject) is stored as a sequence of versions, its voorkomens. Every
+// voorkomen has a validity period (`beginGeldigheid`, and `eindGeldigheid` once a newer version
+// takes over), and an `eindRegistratie` when it was corrected by another voorkomen. Only one
+// voorkomen of an object is current. Its status tells whether the object actually exists: a
+// Verblijfsobject can be planned (`Verblijfsobject gevormd`), in use, demolished
+// (`Verblijfsobject ingetrokken`), and so on.
+//
+// By default only the current voorkomens of the objects that are in use are kept.
+
+use crate::report::Reason;
+
+/// The status of a Verblijfsobject or a Nummeraanduiding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Status {
+    Gevormd,
+    NietGerealiseerd,
+    InGebruikNietIngemeten,
+    InGebruik,
+    Verbouwing,
+    Ingetrokken,
+    BuitenGebruik,
+    TenOnrechteOpgevoerd,
+    NaamgevingUitgegeven,
+    NaamgevingIngetrokken,
+}
+
+impl Status {
+    pub const ALL: [Status; 10] = [
+        Status::Gevormd,
+        Status::NietGerealiseerd,
+        Status::InGebruikNietIngemeten,
+        Status::InGebruik,
+        Status::Verbouwing,
+        Status::Ingetrokken,
+        Status::BuitenGebruik,
+        Status::TenOnrechteOpgevoerd,
+        Status::NaamgevingUitgegeven,
+        Status::NaamgevingIngetrokken,
+    ];
+
+    /// The value of `Objecten:status` in the extract
+    pub const fn text(self) -> &'static str {
+        match self {
+            Status::Gevormd => "Verblijfsobject gevormd",
+            Status::NietGerealiseerd => "Niet gerealiseerd verblijfsobject",
+            Status::InGebruikNietIngemeten => "Verblijfsobject in gebruik (niet ingemeten)",
+            Status::InGebruik => "Verblijfsobject in gebruik",
+            Status::Verbouwing => "Verbouwing verblijfsobject",
+            Status::Ingetrokken => "Verblijfsobject ingetrokken",
+            Status::BuitenGebruik => "Verblijfsobject buiten gebruik",
+            Status::TenOnrechteOpgevoerd => "Verblijfsobject ten onrechte opgevoerd",
+            Status::NaamgevingUitgegeven => "Naamgeving uitgegeven",
+            Status::NaamgevingIngetrokken => "Naamgeving ingetrokken",
+        }
+    }
+
+    /// The name of the status on the command line
+    pub const fn name(self) -> &'static str {
+        match self {
+            Status::Gevormd => "gevormd",
+            Status::NietGerealiseerd => "niet-gerealiseerd",
+            Status::InGebruikNietIngemeten => "in-gebruik-niet-ingemeten",
+            Status::InGebruik => "in-gebruik",
+            Status::Verbouwing => "verbouwing",
+            Status::Ingetrokken => "ingetrokken",
+            Status::BuitenGebruik => "buiten-gebruik",
+            Status::TenOnrechteOpgevoerd => "ten-onrechte-opgevoerd",
+            Status::NaamgevingUitgegeven => "naamgeving-uitgegeven",
+            Status::NaamgevingIngetrokken => "naamgeving-ingetrokken",
+        }
+    }
+
+    pub const fn is_nummeraanduiding(self) -> bool {
+        matches!(
+            self,
+            Status::NaamgevingUitgegeven | Status::NaamgevingIngetrokken
+        )
+    }
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.text())
+    }
+}
+
+impl std::str::FromStr for Status {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Status::ALL
+            .iter()
+            .copied()
+            .find(|status| status.text() == s)
+            .ok_or(())
+    }
+}
+
+/// A set of statuses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statuses(u32);
+
+impl Statuses {
+    pub const ALL: Self = Self((1 << Status::ALL.len()) - 1);
+
+    /// The objects that exist: Verblijfsobjecten that are in use (also while they are being
+    /// renovated), and Nummeraanduidingen that are issued
+    pub const DEFAULT: Self = Self(
+        1 << Status::InGebruikNietIngemeten as u32
+            | 1 << Status::InGebruik as u32
+            | 1 << Status::Verbouwing as u32
+            | 1 << Status::NaamgevingUitgegeven as u32,
+    );
+
+    pub const fn contains(self, status: Status) -> bool {
+        self.0 & (1 << status as u32) != 0
+    }
+
+    /// The statuses with the given command line names, or `all`
+    ///
+    /// Names of Verblijfsobject statuses replace the default statuses of Verblijfsobjecten, and
+    /// likewise for Nummeraanduidingen. So `in-gebruik,buiten-gebruik` still keeps the
+    /// Nummeraanduidingen that are issued.
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Result<Self, String> {
+        let mut verblijfsobjecten = None;
+        let mut nummeraanduidingen = None;
+
+        for name in names {
+            if name == "all" {
+                return Ok(Self::ALL);
+            }
+
+            let status = Status::ALL
+                .iter()
+                .copied()
+                .find(|status| status.name() == name)
+                .ok_or_else(|| format!("unknown status {:?}", name))?;
+
+            let bits = if status.is_nummeraanduiding() {
+                &mut nummeraanduidingen
+            } else {
+                &mut verblijfsobjecten
+            };
+
+            *bits.get_or_insert(0) |= 1 << status as u32;
+        }
+
+        let default = |nummeraanduiding| {
+            Status::ALL
+                .iter()
+                .filter(|status| status.is_nummeraanduiding() == nummeraanduiding)
+                .filter(|status| Self::DEFAULT.contains(**status))
+                .fold(0, |bits, status| bits | 1 << *status as u32)
+        };
+
+        Ok(Self(
+            verblijfsobjecten.unwrap_or_else(|| default(false))
+                | nummeraanduidingen.unwrap_or_else(|| default(true)),
+        ))
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits & Self::ALL.0)
+    }
+}
+
+/// A version of an object, and the period in which it is valid
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Voorkomen {
+    /// `Historie:voorkomenidentificatie`, which counts the versions of an object from 1
+    pub identificatie: u32,
+    /// `Historie:beginGeldigheid` as `yyyymmdd`
+    pub begin_geldigheid: Option<u32>,
+    /// `Historie:eindGeldigheid` as `yyyymmdd`
+    pub eind_geldigheid: Option<u32>,
+    /// whether there is a `Historie:eindRegistratie`: this voorkomen was corrected
+    pub eind_registratie: bool,
+}
+
+impl Voorkomen {
+    /// Whether this voorkomen was corrected, or its validity ended on or before `date`
+    /// (`yyyymmdd`). A voorkomen that has not ended is either current, or becomes current later.
+    /// Without a date, a voorkomen has ended once it has an end.
+    pub fn has_ended(&self, date: Option<u32>) -> bool {
+        if self.eind_registratie {
+            return true;
+        }
+
+        match date {
+            None => self.eind_geldigheid.is_some(),
+            Some(date) => self.eind_geldigheid.is_some_and(|eind| eind <= date),
+        }
+    }
+
+    /// Whether this voorkomen is valid on `date` (`yyyymmdd`)
+    pub fn is_current(&self, date: u32) -> bool {
+        if self.eind_registratie {
+            return false;
+        }
+
+        self.begin_geldigheid.is_none_or(|begin| begin <= date)
+            && self.eind_geldigheid.is_none_or(|eind| date < eind)
+    }
+}
+
+/// Parse a date (`2021-10-08`, or the date part of a timestamp) as `yyyymmdd`
+pub fn parse_date(input: &str) -> Option<u32> {
+    let date = input.get(..10)?;
+    let bytes = date.as_bytes();
+
+    if bytes[4] != b'-' || bytes[7] != b'-' {
+        return None;
+    }
+
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        let digits = &date[range];
+        if digits.bytes().all(|b| b.is_ascii_digit()) {
+            digits.parse().ok()
+        } else {
+            None
+        }
+    };
+
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    Some(year * 10000 + month * 100 + day)
+}
+
+/// Today's date (in UTC) as `yyyymmdd`, for when the date of the extract is unknown
+pub fn today() -> u32 {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    date_from_days(seconds / 86400)
+}
+
+/// The date (`yyyymmdd`) that is `days` days after 1970-01-01
+fn date_from_days(days: u64) -> u32 {
+    // the days since 0000-03-01, so that a leap day is the last day of its year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = match month {
+        0..=9 => (era * 400 + year_of_era, month + 3),
+        _ => (era * 400 + year_of_era + 1, month - 9),
+    };
+
+    (year * 10000 + month * 100 + day) as u32
+}
+
+/// Which objects to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Filter {
+    pub statuses: Statuses,
+    /// the date (`yyyymmdd`) that the objects must be valid on: that of the extract, or today
+    /// when that is unknown
+    pub date: u32,
+}
+
+impl Filter {
+    /// Why an object with this status and voorkomen is not kept, if it is not
+    pub fn skip_reason(&self, status: Status, voorkomen: &Voorkomen) -> Option<Reason> {
+        if !voorkomen.is_current(self.date) {
+            Some(Reason::NotCurrent)
+        } else if !self.statuses.contains(status) {
+            Some(Reason::Status(status))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn statuses() {
+        let names = ["in-gebruik", "buiten-gebruik"];
+        let statuses = Statuses::from_names(names).unwrap();
+
+        assert!(statuses.contains(Status::BuitenGebruik));
+        assert!(!statuses.contains(Status::Verbouwing));
+        assert!(statuses.contains(Status::NaamgevingUitgegeven));
+        assert!(!statuses.contains(Status::NaamgevingIngetrokken));
+
+        assert_eq!(Ok(Statuses::ALL), Statuses::from_names(["all"]));
+        assert_eq!(Ok(Statuses::DEFAULT), Statuses::from_names([]));
+        assert!(Statuses::from_names(["gesloopt"]).is_err());
+
+        assert_eq!(
+            Ok(Status::Ingetrokken),
+            "Verblijfsobject ingetrokken".parse()
+        );
+    }
+
+    #[test]
+    fn current() {
+        assert_eq!(Some(20211008), parse_date("2021-10-08"));
+        assert_eq!(Some(20211008), parse_date("2021-10-08T11:32:29.000"));
+        assert_eq!(None, parse_date("08-10-2021"));
+
+        let voorkomen = Voorkomen {
+            identificatie: 2,
+            begin_geldigheid: Some(20200101),
+            eind_geldigheid: Some(20211008),
+            eind_registratie: false,
+        };
+
+        assert!(voorkomen.is_current(20211007));
+        assert!(!voorkomen.is_current(20211008));
+        assert!(!voorkomen.is_current(20191231));
+
+        assert!(!voorkomen.has_ended(Some(20191231)));
+        assert!(!voorkomen.has_ended(Some(20211007)));
+        assert!(voorkomen.has_ended(Some(20211008)));
+        assert!(voorkomen.has_ended(None));
+
+        let filter = Filter {
+            statuses: Statuses::DEFAULT,
+            date: 20211001,
+        };
+        let reason = filter.skip_reason(Status::Ingetrokken, &voorkomen);
+        assert_eq!(Some(Reason::Status(Status::Ingetrokken)), reason);
+        assert_eq!(None, filter.skip_reason(Status::InGebruik, &voorkomen));
+
+        let corrected = Voorkomen {
+            eind_registratie: true,
+            ..voorkomen
+        };
+        let reason = filter.skip_reason(Status::InGebruik, &corrected);
+        assert_eq!(Some(Reason::NotCurrent), reason);
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(19700101, date_from_days(0));
+        assert_eq!(20000229, date_from_days(11016));
+        assert_eq!(20211008, date_from_days(18908));
+        assert_eq!(20211231, date_from_days(18992));
+        assert!(today() >= 20211008);
+    }
+}